@@ -0,0 +1,204 @@
+# Build the extension into the active environment with `maturin develop`, then run `pytest python/tests`
+
+import math
+
+import numpy as np
+import pytest
+
+import particle_sim as ps
+
+
+def two_body(sim):
+    sun = sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1000.0, 1.0))
+    planet = sim.add_particle(ps.Particle([100.0, 0.0, 0.0], [0.0, math.sqrt(1000.0 / 100.0), 0.0], 1e-6, 0.1))
+    return sun, planet
+
+
+def test_add_and_remove_particles():
+    sim = ps.PSim()
+    sun, planet = two_body(sim)
+    assert len(sim) == 2
+    assert sim.get_particle(sun).mass == 1000.0
+    assert sim.remove_particle(planet) is not None
+    assert sim.get_particle(planet) is None
+    assert len(sim) == 1
+
+
+def test_arrays_line_up_with_ids():
+    sim = ps.PSim()
+    sun, planet = two_body(sim)
+    ids = sim.ids()
+    positions = sim.positions()
+    velocities = sim.velocities()
+    assert positions.shape == (2, 3)
+    assert velocities.shape == (2, 3)
+    row = list(ids).index(planet)
+    np.testing.assert_allclose(positions[row], [100.0, 0.0, 0.0])
+    np.testing.assert_allclose(sim.masses()[list(ids).index(sun)], 1000.0)
+
+
+def test_arrays_follow_the_simulation():
+    sim = ps.PSim()
+    sim.set_gravitational_constant(0.0)
+    sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 1.0, 0.5))
+    positions = sim.positions()
+    assert sim.positions() is positions
+    with pytest.raises(ValueError):
+        positions[0, 0] = 5.0
+    sim.run(10, 0.1)
+    np.testing.assert_allclose(positions[0], [1.0, 0.0, 0.0])
+    sim.add_particle(ps.Particle([10.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1.0, 0.5))
+    sim.step(0.1)
+    # Detached once the particles change, the new set gets new arrays
+    np.testing.assert_allclose(positions[0], [1.0, 0.0, 0.0])
+    assert sim.positions().shape == (2, 3)
+
+
+def test_circular_orbit_conserves_energy():
+    sim = ps.PSim()
+    sim.set_units("nbody")
+    two_body(sim)
+    before = sim.diagnostics()["total_energy"]
+    sim.run(1000, 0.01)
+    after = sim.diagnostics()["total_energy"]
+    assert sim.time == pytest.approx(10.0)
+    assert after == pytest.approx(before, rel=1e-3)
+
+
+def test_gravity_field_pulls_particles_in():
+    sim = ps.PSim()
+    sim.set_gravitational_constant(1.0)
+    sim.add_force_field(ps.ForceField.circle([0.0, 0.0, 0.0], 500.0, mass=100.0))
+    sim.add_particle(ps.Particle([10.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1.0, 0.5))
+    sim.step(0.1)
+    assert sim.velocities()[0][0] < 0.0
+
+
+def test_force_field_requires_one_force_type():
+    with pytest.raises(ValueError):
+        ps.ForceField.circle([0.0, 0.0, 0.0], 1.0)
+    with pytest.raises(ValueError):
+        ps.ForceField.circle([0.0, 0.0, 0.0], 1.0, mass=1.0, force=[1.0, 0.0, 0.0])
+
+
+def test_unknown_units_are_rejected():
+    with pytest.raises(ValueError):
+        ps.PSim().set_units("furlongs")
+
+
+def test_berendsen_thermostat_reaches_target_temperature():
+    sim = ps.PSim()
+    sim.set_gravitational_constant(0.0)
+    sim.seed(1)
+    for i in range(50):
+        sim.add_particle(ps.Particle([i * 10.0, 0.0, 0.0], [0.1 * (i % 3), 0.2, 0.0], 1.0, 0.1))
+    sim.set_thermostat("berendsen", temperature=2.0, coupling_time=0.1)
+    sim.run(500, 0.01)
+    assert sim.temperature == pytest.approx(2.0, rel=1e-3)
+    assert sim.diagnostics()["temperature"] == pytest.approx(sim.temperature)
+
+
+def test_unknown_thermostat_is_rejected():
+    with pytest.raises(ValueError):
+        ps.PSim().set_thermostat("maxwell_demon", temperature=1.0)
+
+
+def test_thermostat_without_a_reachable_target_is_rejected():
+    with pytest.raises(ValueError):
+        ps.PSim().set_thermostat("nose_hoover")
+    with pytest.raises(ValueError):
+        ps.PSim().set_thermostat("berendsen", temperature=1.0, coupling_time=0.0)
+
+
+def test_unbound_elements_are_rejected():
+    sim = ps.PSim()
+    sun = sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1000.0, 1.0))
+    with pytest.raises(ValueError):
+        sim.add_orbiting_particle(sun, ps.KeplerElements(100.0, eccentricity=1.5), 1.0, 0.1)
+    with pytest.raises(ValueError):
+        sim.add_orbiting_particle(12345, ps.KeplerElements(100.0), 1.0, 0.1)
+
+
+def test_unknown_generator_is_rejected():
+    with pytest.raises(ValueError):
+        ps.PSim().generate("big_bang")
+
+
+def test_orbit_analysis_recovers_elements():
+    sim = ps.PSim()
+    sim.set_units("nbody")
+    sun = sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1000.0, 1.0))
+    planet = sim.add_orbiting_particle(sun, ps.KeplerElements(100.0, eccentricity=0.3, true_anomaly=1.0), 1e-6, 0.1)
+    orbit = sim.orbit(planet)
+    assert orbit["central"] == sun
+    assert orbit["bound"]
+    assert orbit["semi_major_axis"] == pytest.approx(100.0)
+    assert orbit["eccentricity"] == pytest.approx(0.3)
+    assert orbit["periapsis"] == pytest.approx(70.0)
+    assert orbit["apoapsis"] == pytest.approx(130.0)
+    assert orbit["period"] == pytest.approx(2 * math.pi * math.sqrt(100.0 ** 3 / (1000.0 + 1e-6)))
+
+
+def test_escaping_particle_is_unbound():
+    sim = ps.PSim()
+    sim.set_units("nbody")
+    sun = sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], 1000.0, 1.0))
+    comet = sim.add_particle(ps.Particle([100.0, 0.0, 0.0], [0.0, 10.0, 0.0], 1e-6, 0.1))
+    orbit = sim.orbit(comet, central=sun)
+    assert not orbit["bound"]
+    assert orbit["eccentricity"] > 1.0
+    assert orbit["apoapsis"] is None and orbit["period"] is None
+    assert sim.orbit(sun, central=sun) is None
+
+
+def test_spatial_queries():
+    sim = ps.PSim()
+    grid = {}
+    for i in range(10):
+        for j in range(10):
+            grid[(i, j)] = sim.add_particle(ps.Particle([i * 10.0, j * 10.0, 0.0], [0.0, 0.0, 0.0], 1.0, 2.0))
+    assert set(sim.particles_within([50.0, 50.0, 0.0], 10.0)) == {grid[(5, 5)], grid[(4, 5)], grid[(6, 5)], grid[(5, 4)], grid[(5, 6)]}
+    assert sim.particles_at([31.0, 41.0, 0.0]) == [grid[(3, 4)]]
+    assert sim.particles_at([35.0, 45.0, 0.0]) == []
+    assert sim.nearest_particle([72.0, 18.0, 0.0]) == grid[(7, 2)]
+    assert set(sim.particles_in_rectangle([15.0, 15.0, 0.0], [35.0, 25.0, 0.0])) == {grid[(2, 2)], grid[(3, 2)]}
+    triangle = [[-5.0, -5.0, 0.0], [26.0, -5.0, 0.0], [-5.0, 26.0, 0.0]]
+    assert set(sim.particles_in_polygon(triangle)) == {grid[(0, 0)], grid[(1, 0)], grid[(0, 1)], grid[(1, 1)], grid[(2, 0)], grid[(0, 2)]}
+    sim.remove_particle(grid[(7, 2)])
+    assert sim.nearest_particle([72.0, 18.0, 0.0]) == grid[(7, 1)]
+    sim.add_force_field(ps.ForceField.circle([0.0, 0.0, 0.0], 5.0, mass=1.0))
+    assert sim.fields_containing([1.0, 1.0, 0.0]) == [0]
+    assert sim.fields_containing([10.0, 0.0, 0.0]) == []
+
+
+def test_statistics_of_a_lattice():
+    sim = ps.PSim()
+    sim.set_gravitational_constant(0.0)
+    for i in range(10):
+        for j in range(10):
+            speed = 1.0 + (i * 10 + j) % 7
+            sim.add_particle(ps.Particle([i * 10.0, j * 10.0, 0.0], [speed, 0.0, 0.0], 1.0, 1.0))
+    speeds = sim.speed_histogram(bins=8)
+    assert sum(speeds["values"]) == 100
+    assert speeds["centers"][-1] == pytest.approx(7.0 - speeds["bin_width"] / 2)
+    assert 50 < sum(speeds["expected"]) < 100
+    assert sum(sim.energy_histogram()["values"]) == 100
+    # Nearest neighbours all sit at the lattice spacing, nothing closer
+    rdf = sim.radial_distribution(15.0, bins=15)
+    assert rdf["expected"] is None
+    assert all(value == 0.0 for value in rdf["values"][:10])
+    assert rdf["values"][10] > 1.0
+    # No forces, so only the kinetic part, 2 K / (d V) over the 90 x 90 box
+    assert sim.virial_pressure() == pytest.approx(2.0 * sim.diagnostics()["kinetic_energy"] / (2 * 90.0 * 90.0))
+
+
+def test_collision_statistics():
+    sim = ps.PSim()
+    sim.set_gravitational_constant(0.0)
+    sim.add_particle(ps.Particle([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], 1.0, 1.0))
+    sim.add_particle(ps.Particle([10.0, 1.0, 0.0], [-1.0, 0.0, 0.0], 1.0, 1.0))
+    collisions = sim.collision_statistics(1000, 0.01)
+    assert collisions["collisions"] == 1
+    assert collisions["frequency"] == pytest.approx(2 / (2 * 10.0))
+    assert collisions["pressure"] > 0.0
+    assert sim.collision_statistics(0, 0.01) is None
@@ -0,0 +1,3 @@
+pub mod psim;
+#[cfg(feature = "python")]
+mod python;
@@ -1,14 +1,107 @@
+#[cfg(feature = "gui")]
 use ggez::{conf, event};
-use crate::psim::visualizer::Visualizer;
+use glam::DVec3;
+use particle_sim::psim;
+use particle_sim::psim::simulator::generators::{Generator, PRESET_NAMES};
+use particle_sim::psim::simulator::psim::PSim;
+use particle_sim::psim::simulator::units::UnitSystem;
+#[cfg(feature = "gui")]
+use particle_sim::psim::visualizer::Visualizer;
 
-mod psim;
+const DEFAULT_HEADLESS_STEPS: u64 = 1000;
+const DEFAULT_DT: f64 = 0.01;
+const HEADLESS_REPORT_EVERY: u64 = 100;
 
+struct Args {
+    headless: bool,
+    steps: u64,
+    dt: f64,
+    script: Option<String>,
+    generator: Option<String>,
+    seed: Option<u64>,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args { headless: false, steps: DEFAULT_HEADLESS_STEPS, dt: DEFAULT_DT, script: None, generator: None, seed: None };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--headless" => args.headless = true,
+            "--steps" => args.steps = iter.next().and_then(|value| value.parse().ok()).expect("--steps expects an integer"),
+            "--dt" => args.dt = iter.next().and_then(|value| value.parse().ok()).expect("--dt expects a number"),
+            "--generate" => args.generator = Some(iter.next().expect("--generate expects a generator name")),
+            "--seed" => args.seed = Some(iter.next().and_then(|value| value.parse().ok()).expect("--seed expects an integer")),
+            _ => args.script = Some(arg),
+        }
+    }
+    args
+}
+
+// A broken script is the user's mistake rather than a bug, so it gets the script's error and no backtrace
+#[cfg(feature = "scripting")]
+fn exit_with_script_error(path: &str, error: &rhai::EvalAltResult) -> ! {
+    eprintln!("{}: {}", path, error);
+    std::process::exit(1);
+}
+
+fn run_headless(args: &Args) {
+    let mut simulator = PSim::new();
+    if let Some(seed) = args.seed {
+        simulator.set_seed(seed);
+    }
+    if let Some(name) = &args.generator {
+        let generator = Generator::preset(name, DVec3::ZERO)
+            .unwrap_or_else(|| panic!("unknown generator '{}', expected one of {}", name, PRESET_NAMES.join(", ")));
+        // The presets are sized for G = 1
+        simulator.set_units(UnitSystem::NBody);
+        generator.populate(&mut simulator, args.seed.unwrap_or_else(rand::random));
+    }
+    #[cfg(feature = "scripting")]
+    {
+        let mut scenario = args.script.as_ref().map(|path| {
+            let scenario = psim::scripting::Scenario::load(path, &mut simulator).unwrap_or_else(|error| exit_with_script_error(path, &error));
+            (path, scenario)
+        });
+        psim::headless::run(&mut simulator, args.steps, args.dt, HEADLESS_REPORT_EVERY, |simulator| {
+            match &mut scenario {
+                Some((path, scenario)) => {
+                    if let Err(error) = scenario.step(simulator) {
+                        exit_with_script_error(path, &error);
+                    }
+                    !scenario.is_stopped()
+                }
+                None => true,
+            }
+        });
+    }
+    #[cfg(not(feature = "scripting"))]
+    psim::headless::run(&mut simulator, args.steps, args.dt, HEADLESS_REPORT_EVERY, |_| true);
+}
+
+#[cfg(not(feature = "gui"))]
+fn main() {
+    // Without a window there is only the headless mode
+    run_headless(&parse_args());
+}
+
+#[cfg(feature = "gui")]
 fn main() {
+    let args = parse_args();
+    if args.headless {
+        run_headless(&args);
+        return;
+    }
+
     let cb = ggez::ContextBuilder::new("window1", "author1")
         .window_setup(conf::WindowSetup::default().title("Window 1"))
-        .window_mode(conf::WindowMode::default().dimensions(1000 as f32, 1000 as f32));
-    let visualizer = Visualizer::new(1000,1000,0.01,false).unwrap();
+        .window_mode(conf::WindowMode::default().dimensions(1000.0, 1000.0));
+    let mut visualizer = Visualizer::new(1000,1000,args.dt,false).unwrap();
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &args.script {
+        if let Err(error) = visualizer.load_scenario(path) {
+            exit_with_script_error(path, &error);
+        }
+    }
     let (ctx, event_loop) = cb.build().unwrap();
     event::run(ctx, event_loop, visualizer);
 }
-
@@ -1,33 +1,106 @@
 use core::fmt;
 
-use ggez::glam::Vec2;
+use glam::{DVec3, Vec2};
 use rand::random;
+use crate::psim::simulator::orbit::OrbitAnalysis;
 use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::units::UnitSystem;
 
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParticleData {
     mass: f64,
     radius: f64,
-    position: Vec2,
-    total_forces: Vec2,
-    velocity: Vec2,
-    acceleration: Vec2,
+    position: DVec3,
+    total_forces: DVec3,
+    velocity: DVec3,
+    acceleration: DVec3,
+    fluid: bool,
+    density: f64,
+    pressure: f64,
+    orbit: Option<OrbitAnalysis>,
+    units: UnitSystem,
 }
 
 impl fmt::Display for ParticleData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Mass: {}\nRadius: {}\nPosition: X:{:.4} Y:{:.4}\nTotal Forces:\nX:{:.4}\nY:{:.4}\nVelocity: X:{:.4} Y:{:.4}\nAcceleration: X:{:.4} Y:{:.4}", self.mass, self.radius, self.position.x, self.position.y, self.total_forces.x, self.total_forces.y, self.velocity.x, self.velocity.y, self.acceleration.x, self.acceleration.y)
+        write!(
+            f,
+            "Mass [{}]: {}\nRadius [{}]: {}\nPosition: X:{:.4} Y:{:.4} Z:{:.4}\nTotal Forces:\nX:{:.4}\nY:{:.4}\nZ:{:.4}\nVelocity [{}/{}]: X:{:.4} Y:{:.4} Z:{:.4}\nAcceleration: X:{:.4} Y:{:.4} Z:{:.4}",
+            self.units.mass_unit(),
+            self.mass,
+            self.units.length_unit(),
+            self.radius,
+            self.position.x, self.position.y, self.position.z,
+            self.total_forces.x, self.total_forces.y, self.total_forces.z,
+            self.units.length_unit(), self.units.time_unit(),
+            self.velocity.x, self.velocity.y, self.velocity.z,
+            self.acceleration.x, self.acceleration.y, self.acceleration.z,
+        )?;
+        if self.fluid {
+            write!(f, "\nDensity: {:.4e}\nPressure: {:.4e}", self.density, self.pressure)?;
+        }
+        if let Some(orbit) = &self.orbit {
+            let length_unit = self.units.length_unit();
+            write!(f, "\nOrbit: {}, e: {:.4}", if orbit.is_bound() { "bound" } else { "unbound" }, orbit.get_eccentricity())?;
+            write!(f, "\nSemi-major axis [{}]: {:.4}\nPeriapsis [{}]: {:.4}", length_unit, orbit.get_semi_major_axis(), length_unit, orbit.get_periapsis())?;
+            if let (Some(apoapsis), Some(period)) = (orbit.get_apoapsis(), orbit.get_period()) {
+                write!(f, "\nApoapsis [{}]: {:.4}\nPeriod [{}]: {:.4}", length_unit, apoapsis, self.units.time_unit(), period)?;
+            }
+        }
+        Ok(())
     }
 }
 
-impl From<Particle> for ParticleData {
-    fn from(particle: Particle) -> Self {
+impl ParticleData {
+    pub fn new(particle: Particle, orbit: Option<OrbitAnalysis>, units: UnitSystem) -> Self {
         ParticleData {
             mass: particle.get_mass(),
             radius: particle.get_radius(),
-            position: particle.get_pos().clone(),
-            total_forces: particle.get_total_forces().clone(),
-            velocity: particle.get_velocity().clone(),
+            position: *particle.get_pos(),
+            total_forces: *particle.get_total_forces(),
+            velocity: *particle.get_velocity(),
             acceleration: particle.get_acceleration(),
+            fluid: particle.is_fluid(),
+            density: particle.get_density(),
+            pressure: particle.get_pressure(),
+            orbit,
+            units,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Plain,
+    Density,
+    Pressure,
+}
+
+impl ColorMode {
+    pub fn next(&self) -> ColorMode {
+        match self {
+            ColorMode::Plain => ColorMode::Density,
+            ColorMode::Density => ColorMode::Pressure,
+            ColorMode::Pressure => ColorMode::Plain,
+        }
+    }
+}
+
+// Which predicted paths are drawn
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PredictionMode {
+    Off,
+    Active,
+    All,
+}
+
+impl PredictionMode {
+    pub fn next(&self) -> PredictionMode {
+        match self {
+            PredictionMode::Off => PredictionMode::Active,
+            PredictionMode::Active => PredictionMode::All,
+            PredictionMode::All => PredictionMode::Off,
         }
     }
 }
@@ -40,6 +113,11 @@ pub struct Gui {
     running: bool,
     active_particle_id: u64,
     active_particle_data: Option<ParticleData>,
+    color_mode: ColorMode,
+    prediction_mode: PredictionMode,
+    show_statistics: bool,
+    // From the scenario script that was stopped by it, shown until the scene is reset
+    scenario_error: Option<String>,
 }
 
 impl Gui {
@@ -52,6 +130,10 @@ impl Gui {
             active_particle_id: random::<u64>(),
             running: false,
             active_particle_data: None,
+            color_mode: ColorMode::Plain,
+            prediction_mode: PredictionMode::Off,
+            show_statistics: false,
+            scenario_error: None,
         }
     }
     pub fn get_size(&self) -> Vec2 {
@@ -75,6 +157,18 @@ impl Gui {
     pub fn get_running(&self) -> bool {
         self.running
     }
+    pub fn get_color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+    pub fn get_prediction_mode(&self) -> PredictionMode {
+        self.prediction_mode
+    }
+    pub fn get_show_statistics(&self) -> bool {
+        self.show_statistics
+    }
+    pub fn get_scenario_error(&self) -> Option<&String> {
+        self.scenario_error.as_ref()
+    }
     pub fn set_size(&mut self, size: Vec2) {
         self.size = size;
     }
@@ -93,8 +187,20 @@ impl Gui {
     pub fn set_running(&mut self, running: bool) {
         self.running = running;
     }
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) {
+        self.color_mode = color_mode;
+    }
+    pub fn set_prediction_mode(&mut self, prediction_mode: PredictionMode) {
+        self.prediction_mode = prediction_mode;
+    }
+    pub fn set_show_statistics(&mut self, show_statistics: bool) {
+        self.show_statistics = show_statistics;
+    }
+    pub fn set_scenario_error(&mut self, scenario_error: Option<String>) {
+        self.scenario_error = scenario_error;
+    }
 
-    pub fn set_active_particle_data(&mut self, particle: Particle) {
-        self.active_particle_data = Some(ParticleData::from(particle));
+    pub fn set_active_particle_data(&mut self, particle: Particle, orbit: Option<OrbitAnalysis>, units: UnitSystem) {
+        self.active_particle_data = Some(ParticleData::new(particle, orbit, units));
     }
 }
\ No newline at end of file
@@ -0,0 +1,33 @@
+use crate::psim::simulator::psim::PSim;
+
+// Steps the simulator without a window, `on_step` runs after forces are gathered and returns false to stop early
+pub fn run(simulator: &mut PSim, steps: u64, dt: f64, report_every: u64, mut on_step: impl FnMut(&mut PSim) -> bool) {
+    for step in 0..steps {
+        simulator.add_forces();
+        if !on_step(simulator) {
+            break;
+        }
+        simulator.step(dt);
+        simulator.drain_events();
+        if report_every > 0 && step % report_every == 0 {
+            report(simulator);
+        }
+    }
+    report(simulator);
+}
+
+fn report(simulator: &PSim) {
+    let diagnostics = simulator.diagnostics();
+    println!(
+        "t={:.6} {} particles={} kinetic={:.6e} potential={:.6e} total={:.6e} [{}] temperature={:.6e} [{}]",
+        simulator.get_time(),
+        simulator.get_units().time_unit(),
+        simulator.get_particles().len(),
+        diagnostics.get_kinetic_energy(),
+        diagnostics.get_potential_energy(),
+        diagnostics.get_total_energy(),
+        simulator.get_units().energy_unit(),
+        diagnostics.get_temperature(),
+        simulator.get_units().temperature_unit(),
+    );
+}
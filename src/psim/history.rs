@@ -0,0 +1,271 @@
+use crate::psim::simulator::forcefield::ForceField;
+use crate::psim::simulator::obstacle::Obstacle;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::psim::PSim;
+
+// Older edits are dropped beyond this, snapshots can hold a whole scene each
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Clone, Copy)]
+pub enum ParticleProperty {
+    Static(bool),
+    Ghost(bool),
+}
+
+impl ParticleProperty {
+    // The same property as currently set on `particle`
+    pub fn current(&self, particle: &Particle) -> ParticleProperty {
+        match self {
+            ParticleProperty::Static(_) => ParticleProperty::Static(particle.is_static()),
+            ParticleProperty::Ghost(_) => ParticleProperty::Ghost(particle.is_ghost()),
+        }
+    }
+
+    fn set(&self, particle: &mut Particle) {
+        match *self {
+            ParticleProperty::Static(is_static) => particle.set_static(is_static),
+            ParticleProperty::Ghost(ghost) => particle.set_ghost(ghost),
+        }
+    }
+}
+
+// A reversible change to the scene. Particles keep moving between an edit and its undo,
+// so undoing a removal brings the particle back as it was when removed
+#[derive(Clone)]
+pub enum Edit {
+    AddParticle { id: u64, particle: Particle },
+    RemoveParticle { id: u64, particle: Particle },
+    AddForceField { index: usize, force_field: ForceField },
+    RemoveForceField { index: usize, force_field: ForceField },
+    AddObstacle { index: usize, obstacle: Obstacle },
+    RemoveObstacle { index: usize, obstacle: Obstacle },
+    SetProperty { id: u64, before: ParticleProperty, after: ParticleProperty },
+    // Whole scene before and after, for edits that also change simulator settings.
+    // Applying one replaces the entire simulator, so undoing it also rewinds the time and every particle's motion
+    // to the moment of the edit, along with anything else that happened since
+    Snapshot { before: Box<PSim>, after: Box<PSim> },
+    // Applied in order, undone in reverse
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    // Edits whose target is already gone are skipped
+    pub fn apply(&self, simulator: &mut PSim) {
+        match self {
+            Edit::AddParticle { id, particle } => simulator.insert_particle(*id, *particle),
+            Edit::RemoveParticle { id, .. } => {
+                simulator.remove_particle(*id);
+            }
+            Edit::AddForceField { index, force_field } => {
+                simulator.insert_force_field((*index).min(simulator.get_force_fields().len()), force_field.clone());
+            }
+            Edit::RemoveForceField { index, .. } => {
+                if *index < simulator.get_force_fields().len() {
+                    simulator.remove_force_field(*index);
+                }
+            }
+            Edit::AddObstacle { index, obstacle } => {
+                simulator.insert_obstacle((*index).min(simulator.get_obstacles().len()), obstacle.clone());
+            }
+            Edit::RemoveObstacle { index, .. } => {
+                if *index < simulator.get_obstacles().len() {
+                    simulator.remove_obstacle(*index);
+                }
+            }
+            Edit::SetProperty { id, after, .. } => {
+                if let Some(particle) = simulator.particles.get_mut(id) {
+                    after.set(particle);
+                }
+            }
+            Edit::Snapshot { after, .. } => *simulator = after.as_ref().clone(),
+            Edit::Group(edits) => {
+                for edit in edits {
+                    edit.apply(simulator);
+                }
+            }
+        }
+    }
+
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::AddParticle { id, particle } => Edit::RemoveParticle { id: *id, particle: *particle },
+            Edit::RemoveParticle { id, particle } => Edit::AddParticle { id: *id, particle: *particle },
+            Edit::AddForceField { index, force_field } => Edit::RemoveForceField { index: *index, force_field: force_field.clone() },
+            Edit::RemoveForceField { index, force_field } => Edit::AddForceField { index: *index, force_field: force_field.clone() },
+            Edit::AddObstacle { index, obstacle } => Edit::RemoveObstacle { index: *index, obstacle: obstacle.clone() },
+            Edit::RemoveObstacle { index, obstacle } => Edit::AddObstacle { index: *index, obstacle: obstacle.clone() },
+            Edit::SetProperty { id, before, after } => Edit::SetProperty { id: *id, before: *after, after: *before },
+            Edit::Snapshot { before, after } => Edit::Snapshot { before: after.clone(), after: before.clone() },
+            Edit::Group(edits) => Edit::Group(edits.iter().rev().map(Edit::inverse).collect()),
+        }
+    }
+}
+
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        History { undo: vec![], redo: vec![] }
+    }
+
+    pub fn apply(&mut self, simulator: &mut PSim, edit: Edit) {
+        edit.apply(simulator);
+        self.record(edit);
+    }
+
+    // For edits that were already made on the simulator
+    pub fn record(&mut self, edit: Edit) {
+        if let Edit::Group(edits) = &edit {
+            if edits.is_empty() {
+                return;
+            }
+        }
+        self.undo.push(edit);
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, simulator: &mut PSim) -> bool {
+        match self.undo.pop() {
+            Some(edit) => {
+                edit.inverse().apply(simulator);
+                self.redo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, simulator: &mut PSim) -> bool {
+        match self.redo.pop() {
+            Some(edit) => {
+                edit.apply(simulator);
+                self.undo.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::DVec3;
+
+    use crate::psim::simulator::forcefield::{ForceType, Shape};
+
+    fn particle(x: f64) -> Particle {
+        Particle::new(DVec3::new(x, 0.0, 0.0), DVec3::ZERO, 1.0, 1.0)
+    }
+
+    fn field() -> ForceField {
+        ForceField::new(DVec3::ZERO, Shape::Circle { radius: 10.0 }, ForceType::Gravity { mass: 1.0 })
+    }
+
+    #[test]
+    fn undo_and_redo_an_edit() {
+        let mut simulator = PSim::new();
+        let mut history = History::new();
+        history.apply(&mut simulator, Edit::AddParticle { id: 7, particle: particle(1.0) });
+        assert!(simulator.get_particles().contains_key(&7));
+        assert!(history.undo(&mut simulator));
+        assert!(simulator.get_particles().is_empty());
+        assert!(!history.can_undo() && history.can_redo());
+        assert!(history.redo(&mut simulator));
+        assert_eq!(simulator.get_particles()[&7].get_pos().x, 1.0);
+        assert!(!history.redo(&mut simulator));
+    }
+
+    #[test]
+    fn group_is_undone_in_reverse() {
+        let mut simulator = PSim::new();
+        let mut history = History::new();
+        let group = Edit::Group(vec![
+            Edit::AddParticle { id: 1, particle: particle(0.0) },
+            Edit::SetProperty { id: 1, before: ParticleProperty::Static(false), after: ParticleProperty::Static(true) },
+            Edit::AddForceField { index: 0, force_field: field() },
+        ]);
+        match group.inverse() {
+            Edit::Group(edits) => {
+                assert!(matches!(edits[0], Edit::RemoveForceField { index: 0, .. }));
+                assert!(matches!(edits[1], Edit::SetProperty { after: ParticleProperty::Static(false), .. }));
+                assert!(matches!(edits[2], Edit::RemoveParticle { id: 1, .. }));
+            }
+            _ => panic!("the inverse of a group is a group"),
+        }
+        history.apply(&mut simulator, group);
+        assert!(simulator.get_particles()[&1].is_static());
+        assert_eq!(simulator.get_force_fields().len(), 1);
+        history.undo(&mut simulator);
+        assert!(simulator.get_particles().is_empty());
+        assert!(simulator.get_force_fields().is_empty());
+    }
+
+    #[test]
+    fn only_the_latest_edits_are_kept() {
+        let mut simulator = PSim::new();
+        let mut history = History::new();
+        for id in 0..HISTORY_LIMIT as u64 + 5 {
+            history.apply(&mut simulator, Edit::AddParticle { id, particle: particle(id as f64) });
+        }
+        let mut undone = 0;
+        while history.undo(&mut simulator) {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+        // The oldest edits can no longer be undone
+        assert_eq!(simulator.get_particles().len(), 5);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut simulator = PSim::new();
+        let mut history = History::new();
+        history.apply(&mut simulator, Edit::AddParticle { id: 1, particle: particle(0.0) });
+        history.undo(&mut simulator);
+        history.apply(&mut simulator, Edit::AddParticle { id: 2, particle: particle(5.0) });
+        assert!(!history.can_redo());
+        assert!(!history.redo(&mut simulator));
+        assert!(!simulator.get_particles().contains_key(&1));
+    }
+
+    #[test]
+    fn undoing_a_snapshot_rewinds_time() {
+        let mut simulator = PSim::new();
+        let mut history = History::new();
+        simulator.add_particle(particle(0.0));
+        simulator.set_time(3.0);
+        let before = Box::new(simulator.clone());
+        simulator = PSim::new();
+        history.record(Edit::Snapshot { before, after: Box::new(simulator.clone()) });
+        simulator.set_time(10.0);
+        history.undo(&mut simulator);
+        assert_eq!(simulator.get_time(), 3.0);
+        assert_eq!(simulator.get_particles().len(), 1);
+    }
+}
@@ -1,4 +1,9 @@
-
+#[cfg(feature = "gui")]
 pub mod visualizer;
 pub mod simulator;
-mod gui;
\ No newline at end of file
+pub mod headless;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod history;
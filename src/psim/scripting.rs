@@ -0,0 +1,352 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use glam::DVec3;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::forcefield::{ForceField, ForceType, Shape};
+use crate::psim::simulator::generators::Generator;
+use crate::psim::simulator::obstacle::Obstacle;
+use crate::psim::simulator::orbit::KeplerElements;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::psim::PSim;
+use crate::psim::simulator::units::UnitSystem;
+
+// Per script call, so a runaway loop in a callback errors out instead of freezing the simulation
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// The simulator is swapped in here for the duration of a script call so the registered functions can reach it,
+// in between calls this holds a placeholder made once at load
+struct ScriptState {
+    simulator: PSim,
+    step_callbacks: Vec<FnPtr>,
+    timers: Vec<(f64, FnPtr)>,
+    stopped: bool,
+    // Set by changes to anything but the particles, which the timeline only stores in keyframes
+    scene_changed: bool,
+}
+
+type SharedState = Rc<RefCell<ScriptState>>;
+
+pub struct Scenario {
+    engine: Engine,
+    ast: AST,
+    state: SharedState,
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>, simulator: &mut PSim) -> Result<Self, Box<EvalAltResult>> {
+        let state = Rc::new(RefCell::new(ScriptState {
+            simulator: PSim::new(),
+            step_callbacks: vec![],
+            timers: vec![],
+            stopped: false,
+            scene_changed: false,
+        }));
+        let engine = create_engine(&state);
+        let ast = engine.compile_file(path.as_ref().to_path_buf())?;
+        let scenario = Scenario { engine, ast, state };
+        // Top level statements set up the scene and register callbacks
+        scenario.with_simulator(simulator, |scenario| scenario.engine.run_ast(&scenario.ast))?;
+        Ok(scenario)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.borrow().stopped
+    }
+
+    // Whether fields, obstacles or settings changed since the last call
+    pub fn take_scene_changed(&mut self) -> bool {
+        std::mem::take(&mut self.state.borrow_mut().scene_changed)
+    }
+
+    // Runs due timers and per-step callbacks, call between PSim::add_forces and PSim::step
+    pub fn step(&mut self, simulator: &mut PSim) -> Result<(), Box<EvalAltResult>> {
+        if self.is_stopped() {
+            return Ok(());
+        }
+        self.with_simulator(simulator, |scenario| {
+            let time = scenario.state.borrow().simulator.get_time();
+            let due: Vec<FnPtr> = {
+                let mut state = scenario.state.borrow_mut();
+                let (due, pending): (Vec<_>, Vec<_>) = state.timers.drain(..).partition(|(at, _)| *at <= time);
+                state.timers = pending;
+                due.into_iter().map(|(_, callback)| callback).collect()
+            };
+            let callbacks = scenario.state.borrow().step_callbacks.clone();
+            for callback in due.iter().chain(callbacks.iter()) {
+                let _: Dynamic = callback.call(&scenario.engine, &scenario.ast, ())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn with_simulator<T>(&self, simulator: &mut PSim, f: impl FnOnce(&Self) -> Result<T, Box<EvalAltResult>>) -> Result<T, Box<EvalAltResult>> {
+        std::mem::swap(simulator, &mut self.state.borrow_mut().simulator);
+        let result = f(self);
+        std::mem::swap(simulator, &mut self.state.borrow_mut().simulator);
+        result
+    }
+}
+
+// Points are written as `[[x, y], [x, y], ...]`
+fn points_from_array(points: Array) -> Result<Vec<DVec3>, Box<EvalAltResult>> {
+    points.into_iter()
+        .map(|point| {
+            let coordinates = point.into_array().map_err(|_| "points must be [x, y] arrays")?;
+            match coordinates.as_slice() {
+                [x, y] => Ok(DVec3::new(x.as_float()?, y.as_float()?, 0.0)),
+                _ => Err("points must be [x, y] arrays".into()),
+            }
+        })
+        .collect()
+}
+
+fn create_engine(state: &SharedState) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    engine.register_type_with_name::<Particle>("Particle")
+        .register_get("x", |particle: &mut Particle| particle.get_pos().x)
+        .register_get("y", |particle: &mut Particle| particle.get_pos().y)
+        .register_get("z", |particle: &mut Particle| particle.get_pos().z)
+        .register_get("vx", |particle: &mut Particle| particle.get_velocity().x)
+        .register_get("vy", |particle: &mut Particle| particle.get_velocity().y)
+        .register_get("vz", |particle: &mut Particle| particle.get_velocity().z)
+        .register_get("mass", |particle: &mut Particle| particle.get_mass())
+        .register_get("radius", |particle: &mut Particle| particle.get_radius());
+
+    let shared = state.clone();
+    engine.register_fn("time", move || shared.borrow().simulator.get_time());
+    let shared = state.clone();
+    engine.register_fn("dt", move || shared.borrow().simulator.get_last_dt());
+    let shared = state.clone();
+    engine.register_fn("stop", move || shared.borrow_mut().stopped = true);
+    let shared = state.clone();
+    engine.register_fn("on_step", move |callback: FnPtr| shared.borrow_mut().step_callbacks.push(callback));
+    let shared = state.clone();
+    engine.register_fn("at", move |time: f64, callback: FnPtr| shared.borrow_mut().timers.push((time, callback)));
+
+    let shared = state.clone();
+    engine.register_fn("set_units", move |name: &str| -> Result<(), Box<EvalAltResult>> {
+        let units = match name {
+            "si" => UnitSystem::SI,
+            "astronomical" => UnitSystem::Astronomical,
+            "nbody" => UnitSystem::NBody,
+            _ => return Err(format!("unknown unit system '{}'", name).into()),
+        };
+        let mut state = shared.borrow_mut();
+        state.simulator.set_units(units);
+        state.scene_changed = true;
+        Ok(())
+    });
+    let shared = state.clone();
+    engine.register_fn("set_gravitational_constant", move |gravitational_constant: f64| {
+        let mut state = shared.borrow_mut();
+        state.simulator.set_units(UnitSystem::Custom { gravitational_constant });
+        state.scene_changed = true;
+    });
+    let shared = state.clone();
+    engine.register_fn("set_dimension", move |dimension: INT| -> Result<(), Box<EvalAltResult>> {
+        let dimension = match dimension {
+            2 => Dimension::Two,
+            3 => Dimension::Three,
+            _ => return Err(format!("unsupported dimension {}", dimension).into()),
+        };
+        let mut state = shared.borrow_mut();
+        state.simulator.set_dimension(dimension);
+        state.scene_changed = true;
+        Ok(())
+    });
+
+    let shared = state.clone();
+    engine.register_fn("add_particle", move |x: f64, y: f64, vx: f64, vy: f64, mass: f64, radius: f64| {
+        let particle = Particle::new(DVec3::new(x, y, 0.0), DVec3::new(vx, vy, 0.0), mass, radius);
+        shared.borrow_mut().simulator.add_particle(particle) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("add_particle", move |x: f64, y: f64, z: f64, vx: f64, vy: f64, vz: f64, mass: f64, radius: f64| {
+        let particle = Particle::new(DVec3::new(x, y, z), DVec3::new(vx, vy, vz), mass, radius);
+        shared.borrow_mut().simulator.add_particle(particle) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("add_static_particle", move |x: f64, y: f64, mass: f64, radius: f64| {
+        let particle = Particle::new_static(DVec3::new(x, y, 0.0), DVec3::ZERO, mass, radius);
+        shared.borrow_mut().simulator.add_particle(particle) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("add_orbiting_particle", move |central: INT, semi_major_axis: f64, eccentricity: f64, argument_of_periapsis: f64, true_anomaly: f64, mass: f64, radius: f64| -> Result<INT, Box<EvalAltResult>> {
+        let elements = KeplerElements::new(semi_major_axis, eccentricity, argument_of_periapsis, true_anomaly);
+        shared.borrow_mut().simulator.add_orbiting_particle(central as u64, &elements, mass, radius)
+            .map(|id| id as INT)
+            .ok_or_else(|| format!("cannot place an orbit around particle {}", central).into())
+    });
+    let shared = state.clone();
+    engine.register_fn("generate", move |name: &str, x: f64, y: f64, seed: INT| -> Result<Array, Box<EvalAltResult>> {
+        let generator = Generator::preset(name, DVec3::new(x, y, 0.0))
+            .ok_or_else(|| format!("unknown generator '{}'", name))?;
+        let ids = generator.populate(&mut shared.borrow_mut().simulator, seed as u64);
+        Ok(ids.into_iter().map(|id| Dynamic::from(id as INT)).collect())
+    });
+    let shared = state.clone();
+    engine.register_fn("remove_particle", move |id: INT| {
+        shared.borrow_mut().simulator.remove_particle(id as u64).is_some()
+    });
+    let shared = state.clone();
+    engine.register_fn("particle", move |id: INT| {
+        match shared.borrow().simulator.get_particles().get(&(id as u64)) {
+            Some(particle) => Dynamic::from(*particle),
+            None => Dynamic::UNIT,
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("particle_ids", move || {
+        shared.borrow().simulator.get_particles().keys().map(|id| Dynamic::from(*id as INT)).collect::<Array>()
+    });
+    let shared = state.clone();
+    engine.register_fn("particle_count", move || shared.borrow().simulator.get_particles().len() as INT);
+    let shared = state.clone();
+    engine.register_fn("push", move |id: INT, fx: f64, fy: f64| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.apply_force(DVec3::new(fx, fy, 0.0));
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("push", move |id: INT, fx: f64, fy: f64, fz: f64| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.apply_force(DVec3::new(fx, fy, fz));
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("set_velocity", move |id: INT, vx: f64, vy: f64| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.set_velocity(DVec3::new(vx, vy, 0.0));
+        }
+    });
+
+    let shared = state.clone();
+    engine.register_fn("add_gravity_field", move |x: f64, y: f64, radius: f64, mass: f64| {
+        let mut state = shared.borrow_mut();
+        state.simulator.add_force_field(ForceField::new(DVec3::new(x, y, 0.0), Shape::Circle { radius }, ForceType::Gravity { mass }));
+        state.scene_changed = true;
+        (state.simulator.get_force_fields().len() - 1) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("add_force_field", move |x: f64, y: f64, width: f64, height: f64, fx: f64, fy: f64| {
+        let mut state = shared.borrow_mut();
+        let force = DVec3::new(fx, fy, 0.0);
+        state.simulator.add_force_field(ForceField::new(DVec3::new(x, y, 0.0), Shape::Rectangle { width, height }, ForceType::Force { force }));
+        state.scene_changed = true;
+        (state.simulator.get_force_fields().len() - 1) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("set_field_active", move |index: INT, active: bool| {
+        let mut state = shared.borrow_mut();
+        if let Some(force_field) = state.simulator.force_fields.get_mut(index as usize) {
+            force_field.set_active(active);
+            state.scene_changed = true;
+        }
+    });
+
+    let shared = state.clone();
+    engine.register_fn("add_segment", move |x1: f64, y1: f64, x2: f64, y2: f64| {
+        let obstacle = Obstacle::Segment { start: DVec3::new(x1, y1, 0.0), end: DVec3::new(x2, y2, 0.0) };
+        let mut state = shared.borrow_mut();
+        state.scene_changed = true;
+        state.simulator.add_obstacle(obstacle) as INT
+    });
+    let shared = state.clone();
+    engine.register_fn("add_polyline", move |points: Array| -> Result<INT, Box<EvalAltResult>> {
+        let obstacle = Obstacle::polyline(points_from_array(points)?).ok_or("polylines need at least 2 points")?;
+        let mut state = shared.borrow_mut();
+        state.scene_changed = true;
+        Ok(state.simulator.add_obstacle(obstacle) as INT)
+    });
+    let shared = state.clone();
+    engine.register_fn("add_polygon", move |points: Array| -> Result<INT, Box<EvalAltResult>> {
+        let obstacle = Obstacle::polygon(points_from_array(points)?).ok_or("polygons need at least 3 points")?;
+        let mut state = shared.borrow_mut();
+        state.scene_changed = true;
+        Ok(state.simulator.add_obstacle(obstacle) as INT)
+    });
+    let shared = state.clone();
+    engine.register_fn("set_ghost", move |id: INT, ghost: bool| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.set_ghost(ghost);
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("set_restitution", move |id: INT, restitution: f64| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.set_restitution(restitution);
+        }
+    });
+    let shared = state.clone();
+    engine.register_fn("set_friction", move |id: INT, friction: f64| {
+        if let Some(particle) = shared.borrow_mut().simulator.particles.get_mut(&(id as u64)) {
+            particle.set_friction(friction);
+        }
+    });
+
+    let shared = state.clone();
+    engine.register_fn("kinetic_energy", move || shared.borrow().simulator.kinetic_energy());
+    let shared = state.clone();
+    engine.register_fn("potential_energy", move || shared.borrow().simulator.potential_energy());
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::psim::headless;
+
+    use super::*;
+
+    const SCENARIO_STEPS: u64 = 500;
+
+    fn script(name: &str, source: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("psim_{}_{}.rhai", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn bundled_scenarios_run_headless() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let mut simulator = PSim::new();
+            let mut scenario = Scenario::load(&path, &mut simulator).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            let mut error = None;
+            headless::run(&mut simulator, SCENARIO_STEPS, 0.01, 0, |simulator| match scenario.step(simulator) {
+                Ok(()) => !scenario.is_stopped(),
+                Err(failure) => {
+                    error = Some(failure);
+                    false
+                }
+            });
+            assert!(error.is_none(), "{}: {}", path.display(), error.unwrap());
+            assert!(simulator.get_time() > 0.0);
+        }
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        let path = script("syntax", "let = ;");
+        assert!(Scenario::load(&path, &mut PSim::new()).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn runaway_callbacks_are_stopped() {
+        let path = script("runaway", "on_step(|| { loop {} });");
+        let mut simulator = PSim::new();
+        let mut scenario = Scenario::load(&path, &mut simulator).unwrap();
+        assert!(scenario.step(&mut simulator).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
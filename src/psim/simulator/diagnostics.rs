@@ -0,0 +1,48 @@
+use core::fmt;
+
+use crate::psim::simulator::thermostat::Thermostat;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostics {
+    kinetic_energy: f64,
+    potential_energy: f64,
+    temperature: f64,
+    thermostat: Option<Thermostat>,
+}
+
+impl Diagnostics {
+    pub fn new(kinetic_energy: f64, potential_energy: f64, temperature: f64, thermostat: Option<Thermostat>) -> Self {
+        Diagnostics { kinetic_energy, potential_energy, temperature, thermostat }
+    }
+
+    pub fn get_kinetic_energy(&self) -> f64 {
+        self.kinetic_energy
+    }
+
+    pub fn get_potential_energy(&self) -> f64 {
+        self.potential_energy
+    }
+
+    pub fn get_total_energy(&self) -> f64 {
+        self.kinetic_energy + self.potential_energy
+    }
+
+    pub fn get_temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn get_thermostat(&self) -> Option<&Thermostat> {
+        self.thermostat.as_ref()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Kinetic: {:.4e}\nPotential: {:.4e}\nTotal: {:.4e}\nTemperature: {:.4e}", self.kinetic_energy, self.potential_energy, self.get_total_energy(), self.temperature)?;
+        if let Some(thermostat) = &self.thermostat {
+            write!(f, "\nThermostat: {}", thermostat)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+use glam::DVec3;
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SimEvent {
+    // `impulse` is the impulse applied to `first`, `second` receives the opposite
+    Collision { first: u64, second: u64, impulse: DVec3, contact_point: DVec3 },
+    ObstacleCollision { particle: u64, obstacle: usize, impulse: DVec3, contact_point: DVec3 },
+    BoundaryExit { id: u64, position: DVec3 },
+    ParticleSpawned { id: u64 },
+    ParticleRemoved { id: u64 },
+    FieldEntered { particle: u64, field: usize },
+    FieldExited { particle: u64, field: usize },
+}
+
+pub type EventListener = Box<dyn FnMut(&SimEvent) + Send>;
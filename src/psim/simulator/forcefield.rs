@@ -1,30 +1,48 @@
-use ggez::glam::Vec2;
-use physical_constants;
-use physical_constants::NEWTONIAN_CONSTANT_OF_GRAVITATION;
+use glam::DVec3;
 use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::softening::Softening;
 
+// Circles and rectangles extend infinitely along z, spheres and boxes are bounded in all three axes
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Shape {
     Circle { radius: f64 },
     Rectangle { width: f64, height: f64 },
+    Sphere { radius: f64 },
+    Box { width: f64, height: f64, depth: f64 },
 }
 
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ForceType {
+    // Inverse square like pair gravity, pulling towards the field's position
     Gravity { mass: f64 },
-    Force { force: Vec2 },
+    Force { force: DVec3 },
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ForceField {
-    position: Vec2,
+    position: DVec3,
     shape: Shape,
     force_type: ForceType,
+    #[cfg_attr(feature = "serde", serde(default = "default_active"))]
+    active: bool,
+}
+
+#[cfg(feature = "serde")]
+fn default_active() -> bool {
+    true
 }
 
 impl ForceField {
-    pub fn new(position: Vec2, shape: Shape, force_type: ForceType) -> Self {
-        ForceField { position, shape, force_type }
+    pub fn new(position: DVec3, shape: Shape, force_type: ForceType) -> Self {
+        ForceField { position, shape, force_type, active: true }
     }
 
-    pub fn get_pos(&self) -> &Vec2 {
+    pub fn get_pos(&self) -> &DVec3 {
         &self.position
     }
 
@@ -35,32 +53,68 @@ impl ForceField {
     pub fn get_force_type(&self) -> &ForceType {
         &self.force_type
     }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
     pub fn affects_particle(&self, particle: &Particle) -> bool {
-        let particle_pos = particle.get_pos();
-        let particle_radius = particle.get_radius();
+        self.active && self.reaches(particle.get_pos(), particle.get_radius())
+    }
+
+    // Whether the point lies inside the shape, active or not
+    pub fn contains(&self, point: &DVec3) -> bool {
+        self.reaches(point, 0.0)
+    }
+
+    // Shape grown by `particle_radius`
+    fn reaches(&self, particle_pos: &DVec3, particle_radius: f64) -> bool {
         match self.shape {
             Shape::Circle { radius } => {
-                let distance = particle_pos.distance(self.position) as f64;
+                let distance = particle_pos.truncate().distance(self.position.truncate());
                 distance < radius + particle_radius
             }
             Shape::Rectangle { width, height } => {
-                (particle_pos.x >= self.position.x - (width / 2.0) as f32 - particle_radius as f32 && particle_pos.x <= self.position.x + (width / 2.0) as f32 + particle_radius as f32) &&
-                    (particle_pos.y >= self.position.y - (height / 2.0) as f32 - particle_radius as f32 && particle_pos.y <= self.position.y + (height / 2.0) as f32 + particle_radius as f32)
+                (particle_pos.x >= self.position.x - (width / 2.0) - particle_radius && particle_pos.x <= self.position.x + (width / 2.0) + particle_radius) &&
+                    (particle_pos.y >= self.position.y - (height / 2.0) - particle_radius && particle_pos.y <= self.position.y + (height / 2.0) + particle_radius)
+            }
+            Shape::Sphere { radius } => {
+                let distance = particle_pos.distance(self.position);
+                distance < radius + particle_radius
+            }
+            Shape::Box { width, height, depth } => {
+                let half_extents = DVec3::new(width, height, depth) / 2.0 + DVec3::splat(particle_radius);
+                let offset = (*particle_pos - self.position).abs();
+                offset.x <= half_extents.x && offset.y <= half_extents.y && offset.z <= half_extents.z
             }
         }
     }
 
-    pub fn calculate_force(&self, particle: &Particle) -> Vec2 {
+    pub fn calculate_force(&self, particle: &Particle, softening: &Softening, gravitational_constant: f64) -> DVec3 {
         match &self.force_type {
             ForceType::Gravity { mass } => {
                 let distance = particle.get_pos().distance(self.position);
-                let force = NEWTONIAN_CONSTANT_OF_GRAVITATION * mass * particle.get_mass() / distance.powi(2) as f64;
-                let direction = (self.position - particle.get_pos().clone()).normalize();
-                direction * (force as f32 / distance)
+                let force = gravitational_constant * mass * particle.get_mass() * softening.force_factor(distance);
+                let direction = (self.position - *particle.get_pos()).normalize_or_zero();
+                direction * force
             }
             ForceType::Force { force } => {
-                force.clone()
+                *force
+            }
+        }
+    }
+
+    pub fn potential_energy(&self, particle: &Particle, softening: &Softening, gravitational_constant: f64) -> f64 {
+        match &self.force_type {
+            ForceType::Gravity { mass } => {
+                let distance = particle.get_pos().distance(self.position);
+                gravitational_constant * mass * particle.get_mass() * softening.potential_factor(distance)
             }
+            ForceType::Force { .. } => 0.0,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,433 @@
+use std::f64::consts::{FRAC_PI_2, TAU};
+
+use glam::DVec3;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::integrator::gaussian_vector;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::psim::PSim;
+
+// Presets behind `Generator::preset`, sized for a 1000 pixel window with G = 1
+const PRESET_PLUMMER_COUNT: usize = 300;
+const PRESET_PLUMMER_MASS: f64 = 1e6;
+const PRESET_PLUMMER_SCALE_RADIUS: f64 = 60.0;
+const PRESET_PARTICLE_RADIUS: f64 = 2.0;
+const PRESET_DISK_COUNT: usize = 400;
+const PRESET_DISK_MASS: f64 = 5e4;
+const PRESET_DISK_SCALE_LENGTH: f64 = 60.0;
+const PRESET_CENTRAL_MASS: f64 = 1e6;
+const PRESET_CENTRAL_RADIUS: f64 = 10.0;
+const PRESET_GAS_COUNT: usize = 400;
+const PRESET_GAS_SIZE: f64 = 400.0;
+const PRESET_GAS_MASS: f64 = 1.0;
+const PRESET_GAS_TEMPERATURE: f64 = 2500.0;
+const PRESET_LATTICE_SIZE: usize = 20;
+const PRESET_LATTICE_SPACING: f64 = 12.0;
+const PRESET_LATTICE_MASS: f64 = 1.0;
+const PRESET_LATTICE_RADIUS: f64 = 3.0;
+const PRESET_LATTICE_TEMPERATURE: f64 = 100.0;
+const PRESET_COLLISION_COUNT: usize = 250;
+const PRESET_COLLISION_SEPARATION: f64 = 500.0;
+const PRESET_COLLISION_IMPACT_PARAMETER: f64 = 150.0;
+const PRESET_COLLISION_SPEED: f64 = 40.0;
+const PRESET_PLANET_MASS: f64 = 1e6;
+const PRESET_PLANET_RADIUS: f64 = 50.0;
+const PRESET_RING_COUNT: usize = 600;
+const PRESET_RING_INNER_RADIUS: f64 = 90.0;
+const PRESET_RING_OUTER_RADIUS: f64 = 200.0;
+const PRESET_RING_PARTICLE_MASS: f64 = 1e-3;
+const PRESET_RING_PARTICLE_RADIUS: f64 = 1.5;
+// Plummer radii are drawn from the full profile, which has a long tail
+const PLUMMER_CUTOFF: f64 = 10.0;
+
+pub const PRESET_NAMES: [&str; 7] = ["plummer", "disk", "gas", "square_lattice", "hexagonal_lattice", "galaxy_collision", "rings"];
+
+// Initial conditions, all centred on `center` and laid out in the xy plane apart from the Plummer sphere
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Generator {
+    // Self-gravitating cluster in virial equilibrium, in 2D the same radii and speeds point within the plane
+    Plummer { center: DVec3, count: usize, total_mass: f64, scale_radius: f64, particle_radius: f64 },
+    // Surface density ∝ exp(-r / scale_length) on circular orbits around a central mass
+    Disk { center: DVec3, count: usize, disk_mass: f64, scale_length: f64, central_mass: f64, central_radius: f64, particle_radius: f64 },
+    // Uniform in a square (a cube in 3D) with Maxwell–Boltzmann velocities
+    Gas { center: DVec3, count: usize, size: f64, mass: f64, radius: f64, temperature: f64 },
+    SquareLattice { center: DVec3, rows: usize, columns: usize, spacing: f64, mass: f64, radius: f64, temperature: f64 },
+    HexagonalLattice { center: DVec3, rows: usize, columns: usize, spacing: f64, mass: f64, radius: f64, temperature: f64 },
+    // Two disks falling towards each other along x, offset by the impact parameter along y
+    GalaxyCollision {
+        center: DVec3,
+        count: usize,
+        disk_mass: f64,
+        scale_length: f64,
+        central_mass: f64,
+        central_radius: f64,
+        particle_radius: f64,
+        separation: f64,
+        impact_parameter: f64,
+        speed: f64,
+    },
+    Rings { center: DVec3, planet_mass: f64, planet_radius: f64, count: usize, inner_radius: f64, outer_radius: f64, particle_mass: f64, particle_radius: f64 },
+}
+
+// Rotation curve of an exponential disk, treating the mass inside r as if it were spherical
+fn disk_circular_speed(radius: f64, gravitational_constant: f64, disk_mass: f64, scale_length: f64, central_mass: f64) -> f64 {
+    let x = radius / scale_length;
+    let enclosed = central_mass + disk_mass * (1.0 - (1.0 + x) * (-x).exp());
+    (gravitational_constant * enclosed / radius).sqrt()
+}
+
+fn isotropic(rng: &mut StdRng, dimension: Dimension) -> DVec3 {
+    gaussian_vector(rng, dimension).normalize_or_zero()
+}
+
+fn planar(angle: f64) -> DVec3 {
+    DVec3::new(angle.cos(), angle.sin(), 0.0)
+}
+
+// Moves the particles into their own centre of mass frame
+fn remove_drift(particles: &mut [Particle]) {
+    let mass: f64 = particles.iter().map(|particle| particle.get_mass()).sum();
+    if mass <= 0.0 {
+        return;
+    }
+    let momentum: DVec3 = particles.iter().map(|particle| *particle.get_velocity() * particle.get_mass()).sum();
+    for particle in particles.iter_mut() {
+        particle.set_velocity(*particle.get_velocity() - momentum / mass);
+    }
+}
+
+// Also makes collisions elastic so the gas keeps its temperature
+fn thermal_velocities(particles: &mut [Particle], rng: &mut StdRng, dimension: Dimension, boltzmann_constant: f64, temperature: f64) {
+    for particle in particles.iter_mut() {
+        particle.set_restitution(1.0);
+    }
+    if temperature <= 0.0 {
+        return;
+    }
+    for particle in particles.iter_mut() {
+        let sigma = (boltzmann_constant * temperature / particle.get_mass()).sqrt();
+        particle.set_velocity(gaussian_vector(rng, dimension) * sigma);
+    }
+    remove_drift(particles);
+}
+
+fn plummer(rng: &mut StdRng, dimension: Dimension, gravitational_constant: f64, count: usize, total_mass: f64, scale_radius: f64, particle_radius: f64) -> Vec<Particle> {
+    let mass = total_mass / count as f64;
+    let mut particles: Vec<Particle> = (0..count).map(|_| {
+        // Aarseth, Hénon & Wielen (1974): invert the cumulative mass for r, then rejection sample v / v_escape
+        let radius = loop {
+            let fraction: f64 = rng.gen_range(1e-10..1.0);
+            let radius = scale_radius / (fraction.powf(-2.0 / 3.0) - 1.0).sqrt();
+            if radius < PLUMMER_CUTOFF * scale_radius {
+                break radius;
+            }
+        };
+        let ratio = loop {
+            let q: f64 = rng.gen();
+            if 0.1 * rng.gen::<f64>() < q * q * (1.0 - q * q).powf(3.5) {
+                break q;
+            }
+        };
+        let escape_speed = (2.0 * gravitational_constant * total_mass).sqrt() * (radius * radius + scale_radius * scale_radius).powf(-0.25);
+        let mut particle = Particle::new(isotropic(rng, dimension) * radius, isotropic(rng, dimension) * ratio * escape_speed, mass, particle_radius);
+        // Stars bouncing off each other shouldn't drain the cluster's energy
+        particle.set_restitution(1.0);
+        particle
+    }).collect();
+    remove_drift(&mut particles);
+    particles
+}
+
+// Central body followed by the disk, which starts just outside it
+fn disk(rng: &mut StdRng, gravitational_constant: f64, count: usize, disk_mass: f64, scale_length: f64, central: Particle, particle_radius: f64) -> Vec<Particle> {
+    let inner_radius = central.get_radius() + particle_radius;
+    let mass = disk_mass / count as f64;
+    let mut particles = vec![central];
+    for _ in 0..count {
+        // Beyond r0 the profile r e^(-r / h) is r0 e^(-s / h) + s e^(-s / h) in s = r - r0, an exponential and a gamma
+        // distribution of shape 2 weighted by their integrals r0 h and h², so r is drawn directly without rejecting any
+        let exponential = |rng: &mut StdRng| -scale_length * (1.0 - rng.gen::<f64>()).ln();
+        let offset = if rng.gen::<f64>() * (inner_radius + scale_length) < inner_radius {
+            exponential(rng)
+        } else {
+            exponential(rng) + exponential(rng)
+        };
+        let radius = inner_radius + offset;
+        let angle = rng.gen_range(0.0..TAU);
+        let speed = disk_circular_speed(radius, gravitational_constant, disk_mass, scale_length, central.get_mass());
+        particles.push(Particle::new(planar(angle) * radius, planar(angle + FRAC_PI_2) * speed, mass, particle_radius));
+    }
+    particles
+}
+
+fn lattice(positions: Vec<DVec3>, mass: f64, radius: f64) -> Vec<Particle> {
+    // Centred on the origin
+    let middle = positions.iter().sum::<DVec3>() / positions.len().max(1) as f64;
+    positions.into_iter().map(|position| Particle::new(position - middle, DVec3::ZERO, mass, radius)).collect()
+}
+
+impl Generator {
+    // The named presets, None for unknown names
+    pub fn preset(name: &str, center: DVec3) -> Option<Self> {
+        let generator = match name {
+            "plummer" => Generator::Plummer {
+                center,
+                count: PRESET_PLUMMER_COUNT,
+                total_mass: PRESET_PLUMMER_MASS,
+                scale_radius: PRESET_PLUMMER_SCALE_RADIUS,
+                particle_radius: PRESET_PARTICLE_RADIUS,
+            },
+            "disk" => Generator::Disk {
+                center,
+                count: PRESET_DISK_COUNT,
+                disk_mass: PRESET_DISK_MASS,
+                scale_length: PRESET_DISK_SCALE_LENGTH,
+                central_mass: PRESET_CENTRAL_MASS,
+                central_radius: PRESET_CENTRAL_RADIUS,
+                particle_radius: PRESET_PARTICLE_RADIUS,
+            },
+            "gas" => Generator::Gas {
+                center,
+                count: PRESET_GAS_COUNT,
+                size: PRESET_GAS_SIZE,
+                mass: PRESET_GAS_MASS,
+                radius: PRESET_PARTICLE_RADIUS,
+                temperature: PRESET_GAS_TEMPERATURE,
+            },
+            "square_lattice" => Generator::SquareLattice {
+                center,
+                rows: PRESET_LATTICE_SIZE,
+                columns: PRESET_LATTICE_SIZE,
+                spacing: PRESET_LATTICE_SPACING,
+                mass: PRESET_LATTICE_MASS,
+                radius: PRESET_LATTICE_RADIUS,
+                temperature: PRESET_LATTICE_TEMPERATURE,
+            },
+            "hexagonal_lattice" => Generator::HexagonalLattice {
+                center,
+                rows: PRESET_LATTICE_SIZE,
+                columns: PRESET_LATTICE_SIZE,
+                spacing: PRESET_LATTICE_SPACING,
+                mass: PRESET_LATTICE_MASS,
+                radius: PRESET_LATTICE_RADIUS,
+                temperature: PRESET_LATTICE_TEMPERATURE,
+            },
+            "galaxy_collision" => Generator::GalaxyCollision {
+                center,
+                count: PRESET_COLLISION_COUNT,
+                disk_mass: PRESET_DISK_MASS,
+                scale_length: PRESET_DISK_SCALE_LENGTH / 2.0,
+                central_mass: PRESET_CENTRAL_MASS / 2.0,
+                central_radius: PRESET_CENTRAL_RADIUS,
+                particle_radius: PRESET_PARTICLE_RADIUS,
+                separation: PRESET_COLLISION_SEPARATION,
+                impact_parameter: PRESET_COLLISION_IMPACT_PARAMETER,
+                speed: PRESET_COLLISION_SPEED,
+            },
+            "rings" => Generator::Rings {
+                center,
+                planet_mass: PRESET_PLANET_MASS,
+                planet_radius: PRESET_PLANET_RADIUS,
+                count: PRESET_RING_COUNT,
+                inner_radius: PRESET_RING_INNER_RADIUS,
+                outer_radius: PRESET_RING_OUTER_RADIUS,
+                particle_mass: PRESET_RING_PARTICLE_MASS,
+                particle_radius: PRESET_RING_PARTICLE_RADIUS,
+            },
+            _ => return None,
+        };
+        Some(generator)
+    }
+
+    // Particles relative to the centre, drawing all randomness from `rng`
+    pub fn particles(&self, rng: &mut StdRng, dimension: Dimension, gravitational_constant: f64, boltzmann_constant: f64) -> Vec<Particle> {
+        match *self {
+            Generator::Plummer { count, total_mass, scale_radius, particle_radius, .. } => {
+                plummer(rng, dimension, gravitational_constant, count, total_mass, scale_radius, particle_radius)
+            }
+            Generator::Disk { count, disk_mass, scale_length, central_mass, central_radius, particle_radius, .. } => {
+                let central = Particle::new(DVec3::ZERO, DVec3::ZERO, central_mass, central_radius);
+                disk(rng, gravitational_constant, count, disk_mass, scale_length, central, particle_radius)
+            }
+            Generator::Gas { count, size, mass, radius, temperature, .. } => {
+                let mut particles: Vec<Particle> = (0..count).map(|_| {
+                    let mut position = DVec3::new(rng.gen(), rng.gen(), rng.gen()) - DVec3::splat(0.5);
+                    if dimension == Dimension::Two {
+                        position.z = 0.0;
+                    }
+                    Particle::new(position * size, DVec3::ZERO, mass, radius)
+                }).collect();
+                thermal_velocities(&mut particles, rng, dimension, boltzmann_constant, temperature);
+                particles
+            }
+            Generator::SquareLattice { rows, columns, spacing, mass, radius, temperature, .. } => {
+                let positions = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| DVec3::new(column as f64, row as f64, 0.0) * spacing))
+                    .collect();
+                let mut particles = lattice(positions, mass, radius);
+                thermal_velocities(&mut particles, rng, dimension, boltzmann_constant, temperature);
+                particles
+            }
+            Generator::HexagonalLattice { rows, columns, spacing, mass, radius, temperature, .. } => {
+                // Every other row shifted by half a spacing, rows √3 / 2 apart
+                let positions = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| DVec3::new(
+                        (column as f64 + 0.5 * (row % 2) as f64) * spacing,
+                        row as f64 * spacing * 3f64.sqrt() / 2.0,
+                        0.0,
+                    )))
+                    .collect();
+                let mut particles = lattice(positions, mass, radius);
+                thermal_velocities(&mut particles, rng, dimension, boltzmann_constant, temperature);
+                particles
+            }
+            Generator::GalaxyCollision { count, disk_mass, scale_length, central_mass, central_radius, particle_radius, separation, impact_parameter, speed, .. } => {
+                let offset = DVec3::new(separation / 2.0, impact_parameter / 2.0, 0.0);
+                let velocity = DVec3::new(speed / 2.0, 0.0, 0.0);
+                let mut particles = vec![];
+                for (offset, velocity) in [(-offset, velocity), (offset, -velocity)] {
+                    let central = Particle::new(DVec3::ZERO, DVec3::ZERO, central_mass, central_radius);
+                    let mut galaxy = disk(rng, gravitational_constant, count, disk_mass, scale_length, central, particle_radius);
+                    for particle in galaxy.iter_mut() {
+                        particle.set_pos(*particle.get_pos() + offset);
+                        particle.set_velocity(*particle.get_velocity() + velocity);
+                    }
+                    particles.append(&mut galaxy);
+                }
+                particles
+            }
+            Generator::Rings { planet_mass, planet_radius, count, inner_radius, outer_radius, particle_mass, particle_radius, .. } => {
+                let mut particles = vec![Particle::new(DVec3::ZERO, DVec3::ZERO, planet_mass, planet_radius)];
+                // Either order describes the same annulus
+                let (inner_radius, outer_radius) = (inner_radius.min(outer_radius), inner_radius.max(outer_radius));
+                for _ in 0..count {
+                    // Uniform over the annulus
+                    let radius = rng.gen_range(inner_radius * inner_radius..=outer_radius * outer_radius).sqrt();
+                    let angle = rng.gen_range(0.0..TAU);
+                    let speed = (gravitational_constant * (planet_mass + particle_mass) / radius).sqrt();
+                    particles.push(Particle::new(planar(angle) * radius, planar(angle + FRAC_PI_2) * speed, particle_mass, particle_radius));
+                }
+                particles
+            }
+        }
+    }
+
+    pub fn get_center(&self) -> DVec3 {
+        match *self {
+            Generator::Plummer { center, .. }
+            | Generator::Disk { center, .. }
+            | Generator::Gas { center, .. }
+            | Generator::SquareLattice { center, .. }
+            | Generator::HexagonalLattice { center, .. }
+            | Generator::GalaxyCollision { center, .. }
+            | Generator::Rings { center, .. } => center,
+        }
+    }
+
+    // Adds the particles to the simulator, the same seed always gives the same particles
+    pub fn populate(&self, simulator: &mut PSim, seed: u64) -> Vec<u64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let particles = self.particles(
+            &mut rng,
+            simulator.get_dimension(),
+            simulator.get_gravitational_constant(),
+            simulator.get_units().boltzmann_constant(),
+        );
+        let center = self.get_center();
+        particles.into_iter()
+            .map(|mut particle| {
+                particle.set_pos(*particle.get_pos() + center);
+                simulator.add_particle(particle)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psim::simulator::units::UnitSystem;
+
+    fn generate(generator: &Generator, seed: u64) -> Vec<Particle> {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::NBody);
+        generator.populate(&mut simulator, seed).iter().map(|id| simulator.get_particles()[id]).collect()
+    }
+
+    fn rings(inner_radius: f64, outer_radius: f64) -> Generator {
+        Generator::Rings {
+            center: DVec3::ZERO,
+            planet_mass: 1000.0,
+            planet_radius: 5.0,
+            count: 200,
+            inner_radius,
+            outer_radius,
+            particle_mass: 1e-3,
+            particle_radius: 0.5,
+        }
+    }
+
+    fn disk_generator(scale_length: f64) -> Generator {
+        Generator::Disk {
+            center: DVec3::ZERO,
+            count: 200,
+            disk_mass: 100.0,
+            scale_length,
+            central_mass: 1000.0,
+            central_radius: 10.0,
+            particle_radius: 1.0,
+        }
+    }
+
+    #[test]
+    fn presets_have_their_particle_counts() {
+        let counts = [300, 401, 400, 400, 400, 502, 601];
+        for (name, count) in PRESET_NAMES.iter().zip(counts) {
+            let generator = Generator::preset(name, DVec3::ZERO).unwrap();
+            assert_eq!(generate(&generator, 1).len(), count, "{}", name);
+        }
+        assert!(Generator::preset("big_bang", DVec3::ZERO).is_none());
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_particles() {
+        for name in PRESET_NAMES {
+            let generator = Generator::preset(name, DVec3::new(500.0, 500.0, 0.0)).unwrap();
+            let positions = |seed| generate(&generator, seed).iter().map(|particle| *particle.get_pos()).collect::<Vec<_>>();
+            assert_eq!(positions(5), positions(5), "{}", name);
+            if name != "square_lattice" && name != "hexagonal_lattice" {
+                assert_ne!(positions(5), positions(6), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn disk_starts_outside_the_central_body() {
+        for scale_length in [60.0, 1e-3, 0.0] {
+            let particles = generate(&disk_generator(scale_length), 2);
+            assert_eq!(particles.len(), 201);
+            assert!(particles[1..].iter().all(|particle| particle.get_pos().length() >= 11.0 - 1e-9));
+        }
+    }
+
+    #[test]
+    fn ring_particles_stay_in_the_annulus_on_circular_orbits() {
+        let particles = generate(&rings(20.0, 40.0), 2);
+        for particle in &particles[1..] {
+            let distance = particle.get_pos().length();
+            assert!((20.0..=40.0).contains(&distance));
+            let speed = particle.get_velocity().length();
+            assert!((speed - ((1000.0 + 1e-3) / distance).sqrt()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn ring_radii_in_either_order_give_the_same_rings() {
+        let positions = |generator| generate(&generator, 3).iter().map(|particle| *particle.get_pos()).collect::<Vec<_>>();
+        assert_eq!(positions(rings(20.0, 40.0)), positions(rings(40.0, 20.0)));
+    }
+}
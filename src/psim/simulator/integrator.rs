@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use glam::DVec3;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::particle::Particle;
+
+// `friction` is a rate, the inverse of the time over which a particle forgets its velocity
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Integrator {
+    // Deterministic update of `Particle::apply_forces`
+    Newtonian,
+    // m dv = F dt - γ m v dt + sqrt(2 γ m k_B T) dW
+    Langevin { friction: f64, temperature: f64 },
+    // Overdamped limit, dx = F / (γ m) dt + sqrt(2 k_B T / (γ m)) dW, velocities only carry the last displacement
+    Brownian { friction: f64, temperature: f64 },
+}
+
+// Unit normal deviates on every axis that is simulated
+pub fn gaussian_vector(rng: &mut StdRng, dimension: Dimension) -> DVec3 {
+    let mut vector = DVec3::new(rng.sample(StandardNormal), rng.sample(StandardNormal), rng.sample(StandardNormal));
+    if dimension == Dimension::Two {
+        vector.z = 0.0;
+    }
+    vector
+}
+
+impl Integrator {
+    // Both stochastic kinds divide by the friction
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Integrator::Newtonian => true,
+            Integrator::Langevin { friction, temperature } | Integrator::Brownian { friction, temperature } => {
+                *friction > 0.0 && *temperature >= 0.0
+            }
+        }
+    }
+
+    // Advances the velocities by dt from the gathered forces, positions are left to the caller
+    pub fn apply(&self, particles: &mut HashMap<u64, Particle>, dimension: Dimension, boltzmann_constant: f64, dt: f64, rng: &mut StdRng) {
+        match self {
+            Integrator::Newtonian => {
+                for particle in particles.values_mut() {
+                    particle.apply_forces(dt);
+                }
+            }
+            Integrator::Langevin { friction, temperature } => {
+                // Exact Ornstein–Uhlenbeck update after the force kick, stable for any friction * dt
+                let decay = (-friction * dt).exp();
+                for particle in particles.values_mut() {
+                    if particle.is_static() {
+                        continue;
+                    }
+                    particle.apply_forces(dt);
+                    let sigma = ((1.0 - decay * decay) * boltzmann_constant * temperature / particle.get_mass()).sqrt();
+                    let velocity = *particle.get_velocity() * decay + gaussian_vector(rng, dimension) * sigma;
+                    particle.set_velocity(velocity);
+                }
+            }
+            Integrator::Brownian { friction, temperature } => {
+                // The noise is turned into a velocity over dt, a zero step has none to carry
+                if dt <= 0.0 {
+                    return;
+                }
+                for particle in particles.values_mut() {
+                    if particle.is_static() {
+                        continue;
+                    }
+                    let mobility = 1.0 / (friction * particle.get_mass());
+                    let drift = *particle.get_total_forces() * mobility;
+                    let diffusion = (2.0 * boltzmann_constant * temperature * mobility / dt).sqrt();
+                    particle.set_velocity(drift + gaussian_vector(rng, dimension) * diffusion);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    use crate::psim::simulator::thermostat;
+
+    // Free particles, so only the integrator moves them
+    fn free_particles(count: usize) -> HashMap<u64, Particle> {
+        (0..count).map(|i| (i as u64, Particle::new(DVec3::new(i as f64 * 1000.0, 0.0, 0.0), DVec3::ZERO, 2.0, 0.01))).collect()
+    }
+
+    fn run(integrator: Integrator, particles: &mut HashMap<u64, Particle>, steps: usize, dt: f64) {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..steps {
+            integrator.apply(particles, Dimension::Two, 1.0, dt, &mut rng);
+            for particle in particles.values_mut() {
+                particle.update_position(dt);
+            }
+        }
+    }
+
+    #[test]
+    fn stochastic_integrators_need_friction() {
+        assert!(Integrator::Newtonian.is_valid());
+        assert!(Integrator::Langevin { friction: 1.0, temperature: 0.0 }.is_valid());
+        assert!(!Integrator::Langevin { friction: 0.0, temperature: 1.0 }.is_valid());
+        assert!(!Integrator::Brownian { friction: -1.0, temperature: 1.0 }.is_valid());
+        assert!(!Integrator::Brownian { friction: 1.0, temperature: -1.0 }.is_valid());
+    }
+
+    #[test]
+    fn brownian_zero_step_leaves_velocities_finite() {
+        let mut particles = HashMap::from([(0, Particle::new(DVec3::ZERO, DVec3::ZERO, 1.0, 1.0))]);
+        let integrator = Integrator::Brownian { friction: 1.0, temperature: 1.0 };
+        integrator.apply(&mut particles, Dimension::Two, 1.0, 0.0, &mut StdRng::seed_from_u64(0));
+        assert!(particles[&0].get_velocity().is_finite());
+    }
+
+    #[test]
+    fn langevin_particles_reach_the_bath_temperature() {
+        let mut particles = free_particles(500);
+        run(Integrator::Langevin { friction: 2.0, temperature: 1.5 }, &mut particles, 1000, 0.01);
+        assert!((thermostat::temperature(&particles, Dimension::Two, 1.0) - 1.5).abs() < 0.15);
+    }
+
+    #[test]
+    fn brownian_particles_diffuse() {
+        let mut particles = free_particles(200);
+        run(Integrator::Brownian { friction: 2.0, temperature: 1.5 }, &mut particles, 1000, 0.01);
+        let mean_square_displacement = particles.iter()
+            .map(|(id, particle)| particle.get_pos().distance_squared(DVec3::new(*id as f64 * 1000.0, 0.0, 0.0)))
+            .sum::<f64>() / 200.0;
+        // 2 d D t with D = k_B T / (friction m)
+        let expected = 2.0 * 2.0 * 1.5 / (2.0 * 2.0) * 10.0;
+        assert!((mean_square_displacement - expected).abs() < 0.2 * expected);
+    }
+}
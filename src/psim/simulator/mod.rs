@@ -1,3 +1,20 @@
 pub mod psim;
 pub mod particle;
-pub mod forcefield;
\ No newline at end of file
+pub mod forcefield;
+pub mod softening;
+pub mod diagnostics;
+pub mod timestep;
+pub mod events;
+pub mod dimension;
+pub mod units;
+pub mod spatial;
+pub mod sph;
+pub mod thermostat;
+pub mod integrator;
+pub mod species;
+pub mod obstacle;
+pub mod orbit;
+pub mod generators;
+pub mod prediction;
+pub mod timeline;
+pub mod statistics;
@@ -0,0 +1,108 @@
+use glam::{DVec2, DVec3};
+
+// Static walls in the xy plane, infinite along z like circular and rectangular force fields
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Obstacle {
+    Segment { start: DVec3, end: DVec3 },
+    Polyline { points: Vec<DVec3> },
+    // Closed, the last point connects back to the first
+    Polygon { points: Vec<DVec3> },
+}
+
+// Where a particle touches an obstacle and how far along `normal` it has to move to stop overlapping
+#[derive(Clone, Copy)]
+pub struct ObstacleContact {
+    pub point: DVec3,
+    pub normal: DVec3,
+    pub depth: f64,
+}
+
+fn cross(a: DVec2, b: DVec2) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+fn closest_point_on_segment(start: DVec2, end: DVec2, point: DVec2) -> DVec2 {
+    let edge = end - start;
+    let length_squared = edge.length_squared();
+    if length_squared == 0.0 {
+        return start;
+    }
+    let t = ((point - start).dot(edge) / length_squared).clamp(0.0, 1.0);
+    start + edge * t
+}
+
+// Contact of a particle that moved from `previous` to `position` with a single edge
+fn segment_contact(start: DVec2, end: DVec2, previous: DVec2, position: DVec2, radius: f64) -> Option<ObstacleContact> {
+    let edge = end - start;
+    let path = position - previous;
+    // Side of the edge the particle came from
+    let mut normal = edge.perp().normalize_or_zero();
+    if normal.dot(previous - start) < 0.0 {
+        normal = -normal;
+    }
+
+    // Centre passed through the edge during the step, put it back on the side it came from
+    let denominator = cross(path, edge);
+    if denominator != 0.0 {
+        let t = cross(start - previous, edge) / denominator;
+        let u = cross(start - previous, path) / denominator;
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+            let point = start + edge * u;
+            return Some(ObstacleContact {
+                point: point.extend(0.0),
+                normal: normal.extend(0.0),
+                depth: (point - position).dot(normal) + radius,
+            });
+        }
+    }
+
+    let closest = closest_point_on_segment(start, end, position);
+    let offset = position - closest;
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
+    }
+    let normal = if distance > 0.0 { offset / distance } else { normal };
+    Some(ObstacleContact {
+        point: closest.extend(0.0),
+        normal: normal.extend(0.0),
+        depth: radius - distance,
+    })
+}
+
+impl Obstacle {
+    // None unless the points make at least one edge
+    pub fn polyline(points: Vec<DVec3>) -> Option<Self> {
+        if points.len() < 2 { None } else { Some(Obstacle::Polyline { points }) }
+    }
+
+    // None unless the points enclose an area, which takes three corners
+    pub fn polygon(points: Vec<DVec3>) -> Option<Self> {
+        if points.len() < 3 { None } else { Some(Obstacle::Polygon { points }) }
+    }
+
+    pub fn edges(&self) -> Vec<(DVec3, DVec3)> {
+        match self {
+            Obstacle::Segment { start, end } => vec![(*start, *end)],
+            Obstacle::Polyline { points } => points.windows(2).map(|pair| (pair[0], pair[1])).collect(),
+            Obstacle::Polygon { points } => (0..points.len())
+                .map(|index| (points[index], points[(index + 1) % points.len()]))
+                .collect(),
+        }
+    }
+
+    // Deepest contact over all edges, the z of the returned point is left at zero
+    pub fn contact(&self, previous: &DVec3, position: &DVec3, radius: f64) -> Option<ObstacleContact> {
+        self.edges().into_iter()
+            .filter_map(|(start, end)| segment_contact(start.truncate(), end.truncate(), previous.truncate(), position.truncate(), radius))
+            .max_by(|a, b| a.depth.total_cmp(&b.depth))
+    }
+
+    pub fn distance(&self, point: &DVec3) -> f64 {
+        self.edges().into_iter()
+            .map(|(start, end)| closest_point_on_segment(start.truncate(), end.truncate(), point.truncate()).distance(point.truncate()))
+            .fold(f64::INFINITY, f64::min)
+    }
+}
@@ -0,0 +1,161 @@
+use glam::DVec3;
+
+// Orbit in the xy plane, angles in radians measured counter-clockwise from +x.
+// Hyperbolic orbits take a negative semi-major axis, like the usual a = -μ / v∞²
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeplerElements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub argument_of_periapsis: f64,
+    pub true_anomaly: f64,
+}
+
+impl KeplerElements {
+    pub fn new(semi_major_axis: f64, eccentricity: f64, argument_of_periapsis: f64, true_anomaly: f64) -> Self {
+        KeplerElements { semi_major_axis, eccentricity, argument_of_periapsis, true_anomaly }
+    }
+
+    pub fn circular(radius: f64, true_anomaly: f64) -> Self {
+        KeplerElements::new(radius, 0.0, 0.0, true_anomaly)
+    }
+
+    pub fn semi_latus_rectum(&self) -> f64 {
+        self.semi_major_axis * (1.0 - self.eccentricity * self.eccentricity)
+    }
+
+    // Position and velocity relative to the central body for μ = G (M + m),
+    // None for parabolic or inconsistent elements and anomalies a hyperbola never reaches
+    pub fn state(&self, gravitational_parameter: f64) -> Option<(DVec3, DVec3)> {
+        let semi_latus_rectum = self.semi_latus_rectum();
+        let denominator = 1.0 + self.eccentricity * self.true_anomaly.cos();
+        if self.eccentricity < 0.0 || semi_latus_rectum <= 0.0 || denominator <= 0.0 || gravitational_parameter <= 0.0 {
+            return None;
+        }
+        let distance = semi_latus_rectum / denominator;
+        let speed = (gravitational_parameter / semi_latus_rectum).sqrt();
+        // Perifocal frame, then rotated by the argument of periapsis
+        let (sin_anomaly, cos_anomaly) = self.true_anomaly.sin_cos();
+        let position = DVec3::new(cos_anomaly, sin_anomaly, 0.0) * distance;
+        let velocity = DVec3::new(-sin_anomaly, self.eccentricity + cos_anomaly, 0.0) * speed;
+        let (sin_periapsis, cos_periapsis) = self.argument_of_periapsis.sin_cos();
+        let rotate = |vector: DVec3| DVec3::new(
+            vector.x * cos_periapsis - vector.y * sin_periapsis,
+            vector.x * sin_periapsis + vector.y * cos_periapsis,
+            0.0,
+        );
+        Some((rotate(position), rotate(velocity)))
+    }
+}
+
+// Two-body orbit of a particle around a central body, from their current relative state
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrbitAnalysis {
+    central: u64,
+    semi_major_axis: f64,
+    eccentricity: f64,
+    periapsis: f64,
+    // Only bound orbits have an apoapsis and a period
+    apoapsis: Option<f64>,
+    period: Option<f64>,
+    bound: bool,
+}
+
+impl OrbitAnalysis {
+    // None when the particle sits on the central body or there is no attraction
+    pub fn new(central: u64, position: DVec3, velocity: DVec3, gravitational_parameter: f64) -> Option<Self> {
+        let distance = position.length();
+        if distance == 0.0 || gravitational_parameter <= 0.0 {
+            return None;
+        }
+        let specific_energy = velocity.length_squared() / 2.0 - gravitational_parameter / distance;
+        let angular_momentum = position.cross(velocity);
+        let eccentricity_vector = velocity.cross(angular_momentum) / gravitational_parameter - position / distance;
+        let eccentricity = eccentricity_vector.length();
+        let semi_latus_rectum = angular_momentum.length_squared() / gravitational_parameter;
+        let bound = specific_energy < 0.0;
+        // Infinite for a parabola, negative for a hyperbola
+        let semi_major_axis = -gravitational_parameter / (2.0 * specific_energy);
+        // From a when bound, p / (1 - e) is 0 / 0 on a radial orbit. A parabola has no finite a
+        let periapsis = if bound { semi_major_axis * (1.0 - eccentricity) } else { semi_latus_rectum / (1.0 + eccentricity) };
+        Some(OrbitAnalysis {
+            central,
+            semi_major_axis,
+            eccentricity,
+            periapsis,
+            apoapsis: if bound { Some(semi_major_axis * (1.0 + eccentricity)) } else { None },
+            period: if bound { Some(std::f64::consts::TAU * (semi_major_axis.powi(3) / gravitational_parameter).sqrt()) } else { None },
+            bound,
+        })
+    }
+
+    pub fn get_central(&self) -> u64 {
+        self.central
+    }
+
+    pub fn get_semi_major_axis(&self) -> f64 {
+        self.semi_major_axis
+    }
+
+    pub fn get_eccentricity(&self) -> f64 {
+        self.eccentricity
+    }
+
+    pub fn get_periapsis(&self) -> f64 {
+        self.periapsis
+    }
+
+    pub fn get_apoapsis(&self) -> Option<f64> {
+        self.apoapsis
+    }
+
+    pub fn get_period(&self) -> Option<f64> {
+        self.period
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn recovers_the_apsides_of_an_ellipse() {
+        let elements = KeplerElements::new(10.0, 0.5, 0.3, 1.0);
+        let (position, velocity) = elements.state(1.0).unwrap();
+        let orbit = OrbitAnalysis::new(0, position, velocity, 1.0).unwrap();
+        assert!(orbit.is_bound());
+        assert!(close(orbit.get_semi_major_axis(), 10.0));
+        assert!(close(orbit.get_eccentricity(), 0.5));
+        assert!(close(orbit.get_periapsis(), 5.0));
+        assert!(close(orbit.get_apoapsis().unwrap(), 15.0));
+    }
+
+    #[test]
+    fn particle_at_rest_falls_on_a_radial_orbit() {
+        let orbit = OrbitAnalysis::new(0, DVec3::new(10.0, 0.0, 0.0), DVec3::ZERO, 1.0).unwrap();
+        assert!(close(orbit.get_eccentricity(), 1.0));
+        assert!(close(orbit.get_periapsis(), 0.0));
+        assert!(close(orbit.get_apoapsis().unwrap(), 10.0));
+        assert!(orbit.get_period().unwrap().is_finite());
+    }
+
+    #[test]
+    fn escaping_particle_has_no_apoapsis() {
+        let elements = KeplerElements::new(-10.0, 2.0, 0.0, 0.5);
+        let (position, velocity) = elements.state(1.0).unwrap();
+        let orbit = OrbitAnalysis::new(0, position, velocity, 1.0).unwrap();
+        assert!(!orbit.is_bound());
+        assert!(close(orbit.get_periapsis(), 10.0));
+        assert_eq!(orbit.get_apoapsis(), None);
+        assert_eq!(orbit.get_period(), None);
+    }
+}
@@ -1,98 +1,248 @@
-use std::f32::consts::PI;
-use ggez::glam::Vec2;
-use physical_constants::NEWTONIAN_CONSTANT_OF_GRAVITATION;
+use std::f64::consts::PI;
+use glam::DVec3;
+use crate::psim::simulator::obstacle::ObstacleContact;
+use crate::psim::simulator::softening::Softening;
+use crate::psim::simulator::species::InteractionMatrix;
+
+pub const DEFAULT_RESTITUTION: f64 = 0.6;
+
+#[cfg(feature = "serde")]
+fn default_restitution() -> f64 {
+    DEFAULT_RESTITUTION
+}
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Contact {
+    pub impulse: DVec3,
+    pub point: DVec3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Particle {
-    position: Vec2,
-    velocity: Vec2,
-    total_forces: Vec2,
+    position: DVec3,
+    #[cfg_attr(feature = "serde", serde(default))]
+    velocity: DVec3,
+    // Only non-zero between gathering forces and stepping
+    #[cfg_attr(feature = "serde", serde(default))]
+    total_forces: DVec3,
     mass: f64,
     radius: f64,
-    is_static: bool
+    #[cfg_attr(feature = "serde", serde(default))]
+    is_static: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    continuous_collision: bool,
+    #[cfg_attr(feature = "serde", serde(default = "default_restitution"))]
+    restitution: f64,
+    // Coulomb coefficient, only used against obstacles
+    #[cfg_attr(feature = "serde", serde(default))]
+    friction: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    species: usize,
+    // Ghosts skip gravity, collisions and species forces with other particles and pass through obstacles
+    #[cfg_attr(feature = "serde", serde(default))]
+    ghost: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fluid: bool,
+    // Filled in by the SPH solver, zero for particles that aren't fluid
+    #[cfg_attr(feature = "serde", serde(default))]
+    density: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pressure: f64,
 }
 
 impl Particle {
-    pub fn new(position: Vec2, velocity: Vec2, mass: f64, radius: f64) -> Self {
-        Particle { position, velocity, total_forces: Vec2::new(0.0, 0.0), mass, radius, is_static: false}
+    pub fn new(position: DVec3, velocity: DVec3, mass: f64, radius: f64) -> Self {
+        Particle { position, velocity, total_forces: DVec3::ZERO, mass, radius, is_static: false, continuous_collision: false, restitution: DEFAULT_RESTITUTION, friction: 0.0, species: 0, ghost: false, fluid: false, density: 0.0, pressure: 0.0 }
     }
 
-    pub fn new_static(position: Vec2, velocity: Vec2, mass: f64, radius: f64) -> Self {
-        Particle { position, velocity, total_forces: Vec2::new(0.0, 0.0), mass, radius, is_static: true}
+    pub fn new_static(position: DVec3, velocity: DVec3, mass: f64, radius: f64) -> Self {
+        Particle { position, velocity, total_forces: DVec3::ZERO, mass, radius, is_static: true, continuous_collision: false, restitution: DEFAULT_RESTITUTION, friction: 0.0, species: 0, ghost: false, fluid: false, density: 0.0, pressure: 0.0 }
     }
 
     fn collides_with(&self, other: &Particle) -> bool {
-        (self.position.distance(other.position) as f64) < self.radius + other.radius
+        self.position.distance(other.position) < self.radius + other.radius
+    }
+
+    // Static particles act as if infinitely heavy
+    fn inverse_mass(&self) -> f64 {
+        if self.is_static {
+            0.0
+        } else {
+            1.0 / self.mass
+        }
+    }
+
+    fn moving_velocity(&self) -> DVec3 {
+        if self.is_static {
+            DVec3::ZERO
+        } else {
+            self.velocity
+        }
     }
 
-    pub fn interact(&mut self, other: &mut Particle) {
-        if !self.is_static && !other.is_static {
-            // Check for collision
-            if self.collides_with(other) {
+    // Static particles pull and block others without moving, ghosts and pairs of static particles are skipped
+    pub fn interacts_with(&self, other: &Particle) -> bool {
+        !(self.ghost || other.ghost || self.is_static && other.is_static)
+    }
+
+    pub fn interact(&mut self, other: &mut Particle, softening: &Softening, gravitational_constant: f64) -> Option<Contact> {
+        let mut contact = None;
+        if self.interacts_with(other) {
+            // Check for collision, fluid particles push each other apart through pressure instead
+            if !(self.fluid && other.fluid) && self.collides_with(other) {
                 // Resolve collision
-                self.resolve_collision(other);
+                contact = Some(self.resolve_collision(other));
             }
 
             // Calculate gravitational force
-            let distance = self.position.distance(other.position) as f64;
-            let force_magnitude = NEWTONIAN_CONSTANT_OF_GRAVITATION * (self.mass * other.mass) / (distance * distance);
+            let distance = self.position.distance(other.position);
+            let force_magnitude = gravitational_constant * (self.mass * other.mass) * softening.force_factor(distance);
 
             // Calculate force direction
             let force_direction = (other.position - self.position).normalize_or_zero();
 
             // Apply gravitational forces to both particles
-            self.apply_force(force_direction * force_magnitude as f32);
-            other.apply_force(-force_direction * force_magnitude as f32);
+            self.apply_force(force_direction * force_magnitude);
+            other.apply_force(-force_direction * force_magnitude);
+        }
+        contact
+    }
+
+    // Replaces gravity when an interaction matrix is set, each side reacts according to its own row
+    pub fn interact_species(&mut self, other: &mut Particle, interactions: &InteractionMatrix) -> Option<Contact> {
+        let mut contact = None;
+        if self.interacts_with(other) {
+            if interactions.collides(self.species, other.species) && self.collides_with(other) {
+                contact = Some(self.resolve_collision(other));
+            }
+
+            let distance = self.position.distance(other.position);
+            let direction = (other.position - self.position).normalize_or_zero();
+            let self_acceleration = interactions.get(self.species, other.species).acceleration(distance);
+            let other_acceleration = interactions.get(other.species, self.species).acceleration(distance);
+            self.apply_force(direction * self_acceleration * self.mass);
+            other.apply_force(-direction * other_acceleration * other.mass);
+        }
+        contact
+    }
+
+    pub fn potential_energy(&self, other: &Particle, softening: &Softening, gravitational_constant: f64) -> f64 {
+        let distance = self.position.distance(other.position);
+        gravitational_constant * (self.mass * other.mass) * softening.potential_factor(distance)
+    }
+
+    pub fn kinetic_energy(&self) -> f64 {
+        0.5 * self.mass * self.velocity.length_squared()
+    }
+
+    // Earliest time within dt at which the two swept circles touch, if they aren't already overlapping
+    pub fn time_of_impact(&self, other: &Particle, dt: f64) -> Option<f64> {
+        let relative_position = other.position - self.position;
+        let relative_velocity = other.moving_velocity() - self.moving_velocity();
+        let contact_distance = self.radius + other.radius;
+
+        let a = relative_velocity.dot(relative_velocity);
+        let b = 2.0 * relative_position.dot(relative_velocity);
+        let c = relative_position.dot(relative_position) - contact_distance * contact_distance;
+        if c <= 0.0 || b >= 0.0 || a == 0.0 {
+            return None;
+        }
+
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let time = (-b - discriminant.sqrt()) / (2.0 * a);
+        if time >= 0.0 && time <= dt {
+            Some(time)
+        } else {
+            None
         }
     }
 
-    fn resolve_collision(&mut self, other: &mut Particle) {
-        const ELASTICITY_COEFFICIENT: f32 = 0.6;
+    pub fn resolve_collision(&mut self, other: &mut Particle) -> Contact {
+        let restitution = 0.5 * (self.restitution + other.restitution);
         // Move particles to avoid overlap
-        let overlap = (self.radius + other.radius) - self.position.distance(other.position) as f64;
+        let overlap = (self.radius + other.radius) - self.position.distance(other.position);
 
         // Ensure particles are separated only if they are overlapping
+        let total_inverse_mass = self.inverse_mass() + other.inverse_mass();
+        if total_inverse_mass == 0.0 {
+            return Contact { impulse: DVec3::ZERO, point: self.position };
+        }
         if overlap > 0.0 {
-            let total_mass = self.mass + other.mass;
-            let self_mass_ratio = self.mass / total_mass;
-            let other_mass_ratio = other.mass / total_mass;
-            let self_move_distance = overlap * self_mass_ratio;
-            let other_move_distance = overlap * other_mass_ratio;
+            // The lighter particle moves further, a static one not at all
+            let self_move_distance = overlap * self.inverse_mass() / total_inverse_mass;
+            let other_move_distance = overlap * other.inverse_mass() / total_inverse_mass;
 
             // Calculate normalized direction vector from self to other
             let direction_self_to_other = (other.position - self.position).normalize_or_zero();
 
             // Move particles explicitly to avoid overlap along the correct direction
-            self.move_by(-direction_self_to_other * other_move_distance as f32);
-            other.move_by(direction_self_to_other * self_move_distance as f32);
+            self.move_by(-direction_self_to_other * self_move_distance);
+            other.move_by(direction_self_to_other * other_move_distance);
         }
 
         // Calculate relative position and velocity after unoverlapping
         let relative_position = other.position - self.position;
-        let relative_velocity = self.velocity - other.velocity;
+        let relative_velocity = self.moving_velocity() - other.moving_velocity();
 
         // Calculate normal vector pointing from self to other
         let normal = relative_position.normalize_or_zero();
 
         // Calculate impulse along the normal direction
-        let impulse = -(1.0 + ELASTICITY_COEFFICIENT) * relative_velocity.dot(normal) / ((1.0 / self.mass) + (1.0 / other.mass)) as f32;
+        let impulse = -(1.0 + restitution) * relative_velocity.dot(normal) / total_inverse_mass;
 
         // Apply impulse to update velocities
-        self.velocity += impulse * normal / self.mass as f32;
-        other.velocity -= impulse * normal / other.mass as f32;
+        self.velocity += impulse * normal * self.inverse_mass();
+        other.velocity -= impulse * normal * other.inverse_mass();
+
+        Contact {
+            impulse: impulse * normal,
+            point: self.position + normal * self.radius,
+        }
+    }
+
+    // Pushes the particle out of the obstacle and reflects the velocity into it, friction slows the sliding part
+    pub fn resolve_obstacle_collision(&mut self, contact: &ObstacleContact) -> Contact {
+        let before = self.velocity;
+        self.move_by(contact.normal * contact.depth);
+        let normal_speed = self.velocity.dot(contact.normal);
+        if normal_speed < 0.0 && !self.is_static {
+            let normal_velocity = contact.normal * normal_speed;
+            let tangential = self.velocity - normal_velocity;
+            let friction_loss = (self.friction * (1.0 + self.restitution) * -normal_speed).min(tangential.length());
+            self.velocity = tangential - tangential.normalize_or_zero() * friction_loss - normal_velocity * self.restitution;
+        }
+        Contact {
+            impulse: (self.velocity - before) * self.mass,
+            point: contact.point,
+        }
     }
 
+    // Drops the out of plane components, used to keep 2D scenes in the z = 0 plane
+    pub fn flatten(&mut self) {
+        self.position.z = 0.0;
+        self.velocity.z = 0.0;
+        self.total_forces.z = 0.0;
+    }
 
-    pub fn move_by(&mut self, delta: Vec2) {
+    pub fn move_by(&mut self, delta: DVec3) {
         if !self.is_static {
-            self.position = self.position + delta;
+            self.position += delta;
         }
     }
 
-    pub fn get_pos(&self) -> &Vec2 {
+    pub fn get_pos(&self) -> &DVec3 {
         &self.position
     }
 
+    pub fn set_pos(&mut self, position: DVec3) {
+        self.position = position;
+    }
+
     pub fn get_mass(&self) -> f64 {
         self.mass
     }
@@ -101,68 +251,168 @@ impl Particle {
         self.radius
     }
 
-    pub fn get_velocity(&self) -> &Vec2 {
+    pub fn is_static(&self) -> bool {
+        self.is_static
+    }
+
+    // Static particles skip `reset_forces`, so forces from before the toggle would otherwise stay until it moves again
+    pub fn set_static(&mut self, is_static: bool) {
+        self.is_static = is_static;
+        self.total_forces = DVec3::ZERO;
+    }
+
+    pub fn get_continuous_collision(&self) -> bool {
+        self.continuous_collision
+    }
+
+    pub fn set_continuous_collision(&mut self, continuous_collision: bool) {
+        self.continuous_collision = continuous_collision;
+    }
+
+    pub fn get_restitution(&self) -> f64 {
+        self.restitution
+    }
+
+    pub fn set_restitution(&mut self, restitution: f64) {
+        self.restitution = restitution;
+    }
+
+    pub fn get_friction(&self) -> f64 {
+        self.friction
+    }
+
+    pub fn set_friction(&mut self, friction: f64) {
+        self.friction = friction;
+    }
+
+    pub fn is_ghost(&self) -> bool {
+        self.ghost
+    }
+
+    pub fn set_ghost(&mut self, ghost: bool) {
+        self.ghost = ghost;
+    }
+
+    pub fn get_species(&self) -> usize {
+        self.species
+    }
+
+    pub fn set_species(&mut self, species: usize) {
+        self.species = species;
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        self.fluid
+    }
+
+    pub fn set_fluid(&mut self, fluid: bool) {
+        self.fluid = fluid;
+    }
+
+    pub fn get_density(&self) -> f64 {
+        self.density
+    }
+
+    pub fn set_density(&mut self, density: f64) {
+        self.density = density;
+    }
+
+    pub fn get_pressure(&self) -> f64 {
+        self.pressure
+    }
+
+    pub fn set_pressure(&mut self, pressure: f64) {
+        self.pressure = pressure;
+    }
+
+    pub fn get_velocity(&self) -> &DVec3 {
         &self.velocity
     }
 
-    pub fn get_acceleration(&self) -> Vec2 {
-        self.total_forces / self.mass as f32
+    pub fn set_velocity(&mut self, velocity: DVec3) {
+        if !self.is_static {
+            self.velocity = velocity;
+        }
     }
 
-    pub fn get_total_forces(&self) -> &Vec2 {
+    pub fn get_acceleration(&self) -> DVec3 {
+        self.total_forces / self.mass
+    }
+
+    pub fn get_total_forces(&self) -> &DVec3 {
         &self.total_forces
     }
 
-    pub fn apply_force(&mut self, external_force: Vec2) {
+    pub fn apply_force(&mut self, external_force: DVec3) {
         if !self.is_static {
             self.total_forces += external_force
         }
     }
 
-    pub fn apply_forces(&mut self) {
+    pub fn apply_forces(&mut self, dt: f64) {
         if !self.is_static {
-            const DAMPING_FACTOR: f32 = 0.00;
-            const FRICTION_COEFFICIENT: f32 = 0.00;
-            const AIR_SPHERE_FRICTION_COEFFICIENT: f32 = 0.47;
-            const AIR_DENSITY: f32 = 0.0;
+            const DAMPING_FACTOR: f64 = 0.00;
+            const FRICTION_COEFFICIENT: f64 = 0.00;
+            const AIR_SPHERE_FRICTION_COEFFICIENT: f64 = 0.47;
+            const AIR_DENSITY: f64 = 0.0;
 
             // Apply damping to velocity
             self.velocity *= 1.0 - DAMPING_FACTOR;
 
             // Apply friction to velocity
-            let friction = -self.velocity.normalize_or_zero() * FRICTION_COEFFICIENT * PI * self.radius as f32 * self.radius as f32;
+            let friction = -self.velocity.normalize_or_zero() * FRICTION_COEFFICIENT * PI * self.radius * self.radius;
             self.apply_force(friction);
 
             // Apply air friction
-            let reference_circumference = std::f32::consts::PI * 2.0 * self.radius as f32;
-            let air_friction = -self.velocity * AIR_SPHERE_FRICTION_COEFFICIENT * AIR_DENSITY * reference_circumference * (2.0 * PI * self.radius as f32);
+            let reference_circumference = std::f64::consts::PI * 2.0 * self.radius;
+            let air_friction = -self.velocity * AIR_SPHERE_FRICTION_COEFFICIENT * AIR_DENSITY * reference_circumference * (2.0 * PI * self.radius);
             self.apply_force(air_friction);
 
             // Calculate acceleration from forces
-            let acceleration = self.total_forces / self.mass as f32;
+            let acceleration = self.total_forces / self.mass;
 
             // Update velocity based on acceleration
-            self.velocity += acceleration;
+            self.velocity += acceleration * dt;
         }
     }
 
     pub fn reset_forces(&mut self) {
         if !self.is_static {
-            self.total_forces = Vec2::new(0.0, 0.0);
+            self.total_forces = DVec3::ZERO;
         }
     }
 
     pub fn update_position(&mut self, dt: f64) {
         if !self.is_static {
-            self.position += self.velocity * dt as f32;
+            self.position += self.velocity * dt;
         }
     }
+}
 
-    pub fn step(&mut self, dt: f64) {
-        if !self.is_static {
-            self.apply_forces();
-            self.update_position(dt);
-            self.reset_forces();
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approaching_pair_meets_when_the_gap_closes() {
+        let first = Particle::new(DVec3::ZERO, DVec3::new(10.0, 0.0, 0.0), 1.0, 1.0);
+        let second = Particle::new(DVec3::new(12.0, 0.0, 0.0), DVec3::ZERO, 1.0, 1.0);
+        let time = first.time_of_impact(&second, 1.0).unwrap();
+        assert!((time - 1.0).abs() < 1e-12);
+        assert_eq!(first.time_of_impact(&second, 0.5), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pair_moving_apart_never_meets() {
+        let first = Particle::new(DVec3::ZERO, DVec3::new(-10.0, 0.0, 0.0), 1.0, 1.0);
+        let second = Particle::new(DVec3::new(12.0, 0.0, 0.0), DVec3::new(10.0, 0.0, 0.0), 1.0, 1.0);
+        assert_eq!(first.time_of_impact(&second, 10.0), None);
+    }
+
+    #[test]
+    fn overlapping_pair_is_left_to_the_discrete_check() {
+        let first = Particle::new(DVec3::ZERO, DVec3::new(10.0, 0.0, 0.0), 1.0, 1.0);
+        let second = Particle::new(DVec3::new(1.0, 0.0, 0.0), DVec3::ZERO, 1.0, 1.0);
+        assert_eq!(first.time_of_impact(&second, 1.0), None);
+    }
+}
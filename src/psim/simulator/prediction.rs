@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use glam::DVec3;
+
+use crate::psim::simulator::psim::PSim;
+
+// Future positions of every particle, starting where it is now and sampled every `stride` steps.
+// Particles that get removed on the way keep the path up to that point
+pub fn predict(simulator: PSim, steps: usize, dt: f64, stride: usize) -> HashMap<u64, Vec<DVec3>> {
+    predict_unless_cancelled(simulator, steps, dt, stride, &AtomicBool::new(false)).unwrap_or_default()
+}
+
+// Gives up with None as soon as `cancel` is set
+fn predict_unless_cancelled(mut simulator: PSim, steps: usize, dt: f64, stride: usize, cancel: &AtomicBool) -> Option<HashMap<u64, Vec<DVec3>>> {
+    let mut paths: HashMap<u64, Vec<DVec3>> = simulator.get_particles().iter()
+        .map(|(id, particle)| (*id, vec![*particle.get_pos()]))
+        .collect();
+    for step in 1..=steps {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        simulator.add_forces();
+        simulator.step(dt);
+        simulator.drain_events();
+        if step % stride.max(1) == 0 || step == steps {
+            for (id, particle) in simulator.get_particles() {
+                if let Some(path) = paths.get_mut(id) {
+                    path.push(*particle.get_pos());
+                }
+            }
+        }
+    }
+    Some(paths)
+}
+
+// Runs `predict` on a copy of the simulator in a background thread, keeping the last finished result
+pub struct Predictor {
+    receiver: Option<Receiver<HashMap<u64, Vec<DVec3>>>>,
+    // Shared with the running thread, which stops at its next step once this is set
+    cancel: Arc<AtomicBool>,
+    paths: HashMap<u64, Vec<DVec3>>,
+    start_time: f64,
+}
+
+impl Default for Predictor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Predictor {
+    pub fn new() -> Self {
+        Predictor { receiver: None, cancel: Arc::new(AtomicBool::new(false)), paths: HashMap::new(), start_time: 0.0 }
+    }
+
+    // A prediction still running is cancelled
+    pub fn start(&mut self, simulator: &PSim, steps: usize, dt: f64, stride: usize) {
+        self.cancel();
+        let (sender, receiver) = mpsc::channel();
+        let simulator = simulator.clone();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.cancel = cancel.clone();
+        self.start_time = simulator.get_time();
+        thread::spawn(move || {
+            if let Some(paths) = predict_unless_cancelled(simulator, steps, dt, stride, &cancel) {
+                let _ = sender.send(paths);
+            }
+        });
+        self.receiver = Some(receiver);
+    }
+
+    fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.receiver = None;
+    }
+
+    // Picks up a finished prediction, true when the paths changed
+    pub fn poll(&mut self) -> bool {
+        let result = match &self.receiver {
+            Some(receiver) => receiver.try_recv(),
+            None => return false,
+        };
+        match result {
+            Ok(paths) => {
+                self.paths = paths;
+                self.receiver = None;
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                self.receiver = None;
+                false
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.cancel();
+        self.paths.clear();
+    }
+
+    // Simulation time the current prediction was started from
+    pub fn get_start_time(&self) -> f64 {
+        self.start_time
+    }
+
+    pub fn get_paths(&self) -> &HashMap<u64, Vec<DVec3>> {
+        &self.paths
+    }
+
+    pub fn get_path(&self, id: u64) -> Option<&Vec<DVec3>> {
+        self.paths.get(&id)
+    }
+}
+
+impl Drop for Predictor {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psim::simulator::particle::Particle;
+    use crate::psim::simulator::units::UnitSystem;
+
+    #[test]
+    fn prediction_matches_the_real_run() {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::NBody);
+        simulator.add_particle(Particle::new(DVec3::ZERO, DVec3::ZERO, 1000.0, 1.0));
+        let planet = simulator.add_particle(Particle::new(DVec3::new(100.0, 0.0, 0.0), DVec3::new(0.0, 10.0_f64.sqrt(), 0.0), 1e-6, 0.1));
+        let paths = predict(simulator.clone(), 100, 0.01, 10);
+        assert_eq!(paths[&planet].len(), 11);
+        assert_eq!(paths[&planet][0], DVec3::new(100.0, 0.0, 0.0));
+        for _ in 0..100 {
+            simulator.add_forces();
+            simulator.step(0.01);
+        }
+        assert_eq!(*paths[&planet].last().unwrap(), *simulator.get_particles()[&planet].get_pos());
+    }
+
+    #[test]
+    fn cancelled_prediction_stops() {
+        let mut simulator = PSim::new();
+        simulator.add_particle(Particle::new(DVec3::ZERO, DVec3::ZERO, 1.0, 1.0));
+        assert!(predict_unless_cancelled(simulator.clone(), usize::MAX, 0.01, 1, &AtomicBool::new(true)).is_none());
+
+        let mut predictor = Predictor::new();
+        predictor.start(&simulator, usize::MAX, 0.01, 1);
+        let cancel = predictor.cancel.clone();
+        assert!(predictor.is_running());
+        predictor.clear();
+        assert!(cancel.load(Ordering::Relaxed));
+        assert!(!predictor.is_running() && !predictor.poll());
+    }
+}
@@ -1,30 +1,573 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use glam::{DVec2, DVec3};
 use multi_mut::HashMapMultiMut;
-use rand::random;
+use rand::rngs::StdRng;
+use rand::{random, SeedableRng};
 
+use crate::psim::simulator::diagnostics::Diagnostics;
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::events::{EventListener, SimEvent};
 use crate::psim::simulator::forcefield::ForceField;
+use crate::psim::simulator::integrator::Integrator;
+use crate::psim::simulator::obstacle::Obstacle;
+use crate::psim::simulator::orbit::{KeplerElements, OrbitAnalysis};
 use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::softening::Softening;
+use crate::psim::simulator::spatial::{self, SpatialGrid};
+use crate::psim::simulator::species::InteractionMatrix;
+use crate::psim::simulator::sph::Sph;
+use crate::psim::simulator::thermostat::{self, Thermostat};
+use crate::psim::simulator::timestep::{Timestep, TimestepCriterion};
+use crate::psim::simulator::units::UnitSystem;
 
+// Nearest particle searches grow this many times from one grid cell before scanning everything
+const NEAREST_SEARCH_DOUBLINGS: usize = 16;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bounds {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "limits::serialize", deserialize_with = "limits::deserialize_min"))]
+    pub min: DVec3,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "limits::serialize", deserialize_with = "limits::deserialize_max"))]
+    pub max: DVec3,
+}
+
+// JSON has no infinity, so an unbounded side is written as null and read back as infinite in its direction
+#[cfg(feature = "serde")]
+mod limits {
+    use glam::DVec3;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(limit: &DVec3, serializer: S) -> Result<S::Ok, S::Error> {
+        limit.to_array().map(|value| if value.is_finite() { Some(value) } else { None }).serialize(serializer)
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D, unbounded: f64) -> Result<DVec3, D::Error> {
+        let limit = <[Option<f64>; 3]>::deserialize(deserializer)?;
+        Ok(DVec3::from_array(limit.map(|value| value.unwrap_or(unbounded))))
+    }
+
+    pub fn deserialize_min<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DVec3, D::Error> {
+        deserialize(deserializer, f64::NEG_INFINITY)
+    }
+
+    pub fn deserialize_max<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DVec3, D::Error> {
+        deserialize(deserializer, f64::INFINITY)
+    }
+}
+
+impl Bounds {
+    pub fn new(min: DVec3, max: DVec3) -> Self {
+        Bounds { min, max }
+    }
+
+    pub fn contains(&self, point: &DVec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+// What one step hands to the next besides the particles, so a run can carry on exactly from a recorded state.
+// The accelerations behind the error estimate are left out, they only shape the next adaptive step
+#[derive(Clone)]
+pub struct StepState {
+    rng: StdRng,
+    thermostat_friction: f64,
+    last_dt: f64,
+}
+
+// Missing fields fall back to the values of `PSim::new`, so partial configs load as well as full snapshots.
+// Derived as a remote of itself so the impls below can rebuild the skipped index once loaded
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(remote = "Self", default))]
 pub struct PSim {
     pub particles: HashMap<u64,Particle>,
-    pub force_fields: Vec<ForceField>
+    pub force_fields: Vec<ForceField>,
+    obstacles: Vec<Obstacle>,
+    dimension: Dimension,
+    units: UnitSystem,
+    softening: Softening,
+    timestep: Timestep,
+    last_dt: f64,
+    time: f64,
+    // Only feeds the error estimate of the next adaptive step
+    #[cfg_attr(feature = "serde", serde(skip))]
+    previous_accelerations: HashMap<u64, DVec3>,
+    // Where each particle ended the last step, so obstacle sweeps also cover pushes from collisions in between
+    #[cfg_attr(feature = "serde", serde(skip))]
+    previous_positions: HashMap<u64, DVec3>,
+    continuous_collision: bool,
+    bounds: Option<Bounds>,
+    field_occupancy: HashSet<(u64, usize)>,
+    sph: Option<Sph>,
+    interactions: Option<InteractionMatrix>,
+    integrator: Integrator,
+    thermostat: Option<Thermostat>,
+    // Nosé–Hoover friction, carried between steps
+    thermostat_friction: f64,
+    // Reseeded from entropy when loaded, call `set_seed` again for reproducible runs
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rng: StdRng,
+    // Particle positions flattened onto the xy plane for the spatial queries, rebuilt every step
+    #[cfg_attr(feature = "serde", serde(skip))]
+    particle_index: SpatialGrid<u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    largest_radius: f64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    events: Vec<SimEvent>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    listeners: Vec<EventListener>,
+}
+
+fn flatten(position: &DVec3) -> DVec3 {
+    position.truncate().extend(0.0)
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PSim {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PSim::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PSim {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut simulator = PSim::deserialize(deserializer)?;
+        simulator.rebuild_index();
+        Ok(simulator)
+    }
+}
+
+impl Default for PSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Listeners can't be cloned, so the copy starts without any and with no pending events
+impl Clone for PSim {
+    fn clone(&self) -> Self {
+        PSim {
+            particles: self.particles.clone(),
+            force_fields: self.force_fields.clone(),
+            obstacles: self.obstacles.clone(),
+            dimension: self.dimension,
+            units: self.units,
+            softening: self.softening,
+            timestep: self.timestep,
+            last_dt: self.last_dt,
+            time: self.time,
+            previous_accelerations: self.previous_accelerations.clone(),
+            previous_positions: self.previous_positions.clone(),
+            continuous_collision: self.continuous_collision,
+            bounds: self.bounds,
+            field_occupancy: self.field_occupancy.clone(),
+            sph: self.sph,
+            interactions: self.interactions.clone(),
+            integrator: self.integrator,
+            thermostat: self.thermostat,
+            thermostat_friction: self.thermostat_friction,
+            rng: self.rng.clone(),
+            particle_index: self.particle_index.clone(),
+            largest_radius: self.largest_radius,
+            events: vec![],
+            listeners: vec![],
+        }
+    }
 }
 
 impl PSim {
     pub fn new() -> Self {
-        PSim { particles: HashMap::new() , force_fields: vec![]}
+        PSim {
+            particles: HashMap::new(),
+            force_fields: vec![],
+            obstacles: vec![],
+            dimension: Dimension::Two,
+            units: UnitSystem::SI,
+            softening: Softening::None,
+            timestep: Timestep::Fixed,
+            last_dt: 0.0,
+            time: 0.0,
+            previous_accelerations: HashMap::new(),
+            previous_positions: HashMap::new(),
+            continuous_collision: false,
+            bounds: None,
+            field_occupancy: HashSet::new(),
+            sph: None,
+            interactions: None,
+            integrator: Integrator::Newtonian,
+            thermostat: None,
+            thermostat_friction: 0.0,
+            rng: StdRng::from_entropy(),
+            particle_index: SpatialGrid::default(),
+            largest_radius: 0.0,
+            events: vec![],
+            listeners: vec![],
+        }
+    }
+
+    pub fn get_dimension(&self) -> Dimension {
+        self.dimension
+    }
+
+    pub fn set_dimension(&mut self, dimension: Dimension) {
+        self.dimension = dimension;
+    }
+
+    pub fn get_units(&self) -> &UnitSystem {
+        &self.units
+    }
+
+    pub fn set_units(&mut self, units: UnitSystem) {
+        self.units = units;
+    }
+
+    pub fn get_gravitational_constant(&self) -> f64 {
+        self.units.gravitational_constant()
+    }
+
+    pub fn get_softening(&self) -> &Softening {
+        &self.softening
+    }
+
+    pub fn set_softening(&mut self, softening: Softening) {
+        self.softening = softening;
+    }
+
+    pub fn get_timestep(&self) -> &Timestep {
+        &self.timestep
+    }
+
+    pub fn set_timestep(&mut self, timestep: Timestep) {
+        self.timestep = timestep;
+    }
+
+    pub fn get_continuous_collision(&self) -> bool {
+        self.continuous_collision
+    }
+
+    pub fn set_continuous_collision(&mut self, continuous_collision: bool) {
+        self.continuous_collision = continuous_collision;
+    }
+
+    pub fn get_last_dt(&self) -> f64 {
+        self.last_dt
+    }
+
+    pub fn get_time(&self) -> f64 {
+        self.time
     }
 
-    pub fn add_particle(&mut self, particle: Particle) {
+    pub fn set_time(&mut self, time: f64) {
+        self.time = time;
+    }
+
+    pub fn get_bounds(&self) -> Option<&Bounds> {
+        self.bounds.as_ref()
+    }
+
+    // Particles leaving the bounds are removed at the end of each step
+    pub fn set_bounds(&mut self, bounds: Option<Bounds>) {
+        self.bounds = bounds;
+    }
+
+    pub fn get_sph(&self) -> Option<&Sph> {
+        self.sph.as_ref()
+    }
+
+    // Fluid particles only feel pressure and viscosity while a solver is set
+    pub fn set_sph(&mut self, sph: Option<Sph>) {
+        self.sph = sph;
+    }
+
+    pub fn get_thermostat(&self) -> Option<&Thermostat> {
+        self.thermostat.as_ref()
+    }
+
+    pub fn set_thermostat(&mut self, thermostat: Option<Thermostat>) {
+        self.thermostat = thermostat;
+        self.thermostat_friction = 0.0;
+    }
+
+    pub fn get_interactions(&self) -> Option<&InteractionMatrix> {
+        self.interactions.as_ref()
+    }
+
+    // Pairs follow the species matrix instead of gravity while one is set, force fields still apply
+    pub fn set_interactions(&mut self, interactions: Option<InteractionMatrix>) {
+        self.interactions = interactions;
+    }
+
+    pub fn get_integrator(&self) -> &Integrator {
+        &self.integrator
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    pub fn get_step_state(&self) -> StepState {
+        StepState {
+            rng: self.rng.clone(),
+            thermostat_friction: self.thermostat_friction,
+            last_dt: self.last_dt,
+        }
+    }
+
+    pub fn set_step_state(&mut self, state: StepState) {
+        self.rng = state.rng;
+        self.thermostat_friction = state.thermostat_friction;
+        self.last_dt = state.last_dt;
+    }
+
+    // Seeds the generator behind the stochastic integrators and thermostats
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn temperature(&self) -> f64 {
+        thermostat::temperature(&self.particles, self.dimension, self.units.boltzmann_constant())
+    }
+
+    pub fn add_listener<F: FnMut(&SimEvent) + Send + 'static>(&mut self, listener: F) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn get_events(&self) -> &Vec<SimEvent> {
+        &self.events
+    }
+
+    // Events accumulate until drained, consumers that only use listeners should still drain periodically
+    pub fn drain_events(&mut self) -> Vec<SimEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn emit(&mut self, event: SimEvent) {
+        for listener in &mut self.listeners {
+            listener(&event);
+        }
+        self.events.push(event);
+    }
+
+    pub fn add_particle(&mut self, particle: Particle) -> u64 {
         let id = random::<u64>();
+        self.insert_particle(id, particle);
+        id
+    }
+
+    // Adds a particle under a known id, replacing any particle that already has it
+    pub fn insert_particle(&mut self, id: u64, particle: Particle) {
+        if let Some(replaced) = self.particles.get(&id) {
+            self.particle_index.remove(id, &flatten(replaced.get_pos()));
+        }
+        self.particle_index.insert(id, flatten(particle.get_pos()));
+        self.largest_radius = self.largest_radius.max(particle.get_radius());
         self.particles.insert(id,particle);
+        // Resize the grid cells whenever the count doubles, so bulk spawning before the first step stays cheap
+        if self.particles.len().is_power_of_two() {
+            self.rebuild_index();
+        }
+        self.emit(SimEvent::ParticleSpawned { id });
+    }
+
+    pub fn remove_particle(&mut self, id: u64) -> Option<Particle> {
+        let particle = self.particles.remove(&id)?;
+        self.particle_index.remove(id, &flatten(particle.get_pos()));
+        self.field_occupancy.retain(|(particle_id, _)| *particle_id != id);
+        self.emit(SimEvent::ParticleRemoved { id });
+        Some(particle)
+    }
+
+    // Places a new particle on the given orbit around `central`, moving along with it.
+    // None if the central body doesn't exist or the elements don't describe a reachable point
+    pub fn add_orbiting_particle(&mut self, central: u64, elements: &KeplerElements, mass: f64, radius: f64) -> Option<u64> {
+        let body = self.particles.get(&central)?;
+        let gravitational_parameter = self.get_gravitational_constant() * (body.get_mass() + mass);
+        let (position, velocity) = elements.state(gravitational_parameter)?;
+        let particle = Particle::new(*body.get_pos() + position, *body.get_velocity() + velocity, mass, radius);
+        Some(self.add_particle(particle))
+    }
+
+    // Body pulling hardest on a point, which is the one to orbit there
+    pub fn dominant_body(&self, point: &DVec3) -> Option<u64> {
+        self.particles.iter()
+            .filter(|(_, particle)| !particle.is_ghost() && particle.get_pos() != point)
+            .max_by(|(_, a), (_, b)| {
+                let pull = |particle: &Particle| particle.get_mass() / particle.get_pos().distance_squared(*point);
+                pull(a).total_cmp(&pull(b))
+            })
+            .map(|(id, _)| *id)
+    }
+
+    // Orbit of `id` around `central`, or around the dominant body at its position when None
+    pub fn orbit(&self, id: u64, central: Option<u64>) -> Option<OrbitAnalysis> {
+        let particle = self.particles.get(&id)?;
+        // The particle itself sits at its own position, so it is never its own dominant body
+        let central = central.or_else(|| self.dominant_body(particle.get_pos()))?;
+        if central == id {
+            return None;
+        }
+        let body = self.particles.get(&central)?;
+        OrbitAnalysis::new(
+            central,
+            *particle.get_pos() - *body.get_pos(),
+            *particle.get_velocity() - *body.get_velocity(),
+            self.get_gravitational_constant() * (body.get_mass() + particle.get_mass()),
+        )
+    }
+
+    // `step`, `insert_particle` and `remove_particle` keep the index up to date,
+    // call this after moving particles by hand. Deserializing calls it as well
+    pub fn rebuild_index(&mut self) {
+        let (min, max) = self.particles.values()
+            .map(|particle| particle.get_pos().truncate())
+            .fold((DVec2::INFINITY, DVec2::NEG_INFINITY), |(min, max), position| (min.min(position), max.max(position)));
+        // Around one particle per cell when they are spread evenly
+        let extent = (max - min).max_element();
+        let cell_size = if extent.is_finite() && extent > 0.0 { extent / (self.particles.len() as f64).sqrt() } else { 1.0 };
+        self.particle_index = SpatialGrid::new(cell_size);
+        self.largest_radius = 0.0;
+        for (id, particle) in &self.particles {
+            self.particle_index.insert(*id, flatten(particle.get_pos()));
+            self.largest_radius = self.largest_radius.max(particle.get_radius());
+        }
+    }
+
+    // For particles that were replaced wholesale, e.g. by rewinding: forgets what the last step left behind
+    // and recomputes field occupancy from where the particles are now, without emitting enter or exit events
+    pub fn clear_step_caches(&mut self) {
+        self.previous_accelerations.clear();
+        self.previous_positions.clear();
+        self.field_occupancy = self.particles.iter()
+            .flat_map(|(id, particle)| self.force_fields.iter().enumerate()
+                .filter(|(_, force_field)| force_field.affects_particle(particle))
+                .map(move |(index, _)| (*id, index)))
+            .collect();
+        self.rebuild_index();
+    }
+
+    // Particles whose centre is within `radius` of `point`
+    pub fn particles_within(&self, point: &DVec3, radius: f64) -> Vec<u64> {
+        self.particle_index.neighbours(&flatten(point), radius).into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| self.particles.get(id).is_some_and(|particle| particle.get_pos().distance(*point) <= radius))
+            .collect()
+    }
+
+    // Particles drawn over `point` when looking down the z axis, the visualizer's hit test
+    pub fn particles_at(&self, point: &DVec3) -> Vec<u64> {
+        self.particle_index.neighbours(&flatten(point), self.largest_radius).into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| self.particles.get(id).is_some_and(|particle| {
+                particle.get_pos().truncate().distance(point.truncate()) <= particle.get_radius()
+            }))
+            .collect()
+    }
+
+    // Centres inside the xy rectangle spanned by `min` and `max`, at any depth
+    pub fn particles_in_rectangle(&self, min: &DVec3, max: &DVec3) -> Vec<u64> {
+        let (low, high) = (flatten(&min.min(*max)), flatten(&min.max(*max)));
+        self.particle_index.within_box(&low, &high).into_iter()
+            .filter(|id| self.particles.get(id).is_some_and(|particle| {
+                let position = flatten(particle.get_pos());
+                position.cmpge(low).all() && position.cmple(high).all()
+            }))
+            .collect()
+    }
+
+    // Centres inside the closed xy polygon, at any depth
+    pub fn particles_in_polygon(&self, points: &[DVec3]) -> Vec<u64> {
+        if points.len() < 3 {
+            return vec![];
+        }
+        let min = points.iter().fold(DVec3::INFINITY, |min, point| min.min(*point));
+        let max = points.iter().fold(DVec3::NEG_INFINITY, |max, point| max.max(*point));
+        self.particles_in_rectangle(&min, &max).into_iter()
+            .filter(|id| spatial::polygon_contains(points, self.particles[id].get_pos()))
+            .collect()
+    }
+
+    pub fn nearest_particle(&self, point: &DVec3) -> Option<u64> {
+        if self.particles.is_empty() {
+            return None;
+        }
+        let nearest = |ids: Vec<u64>| ids.into_iter()
+            .map(|id| (id, self.particles[&id].get_pos().distance(*point)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        // Widen the search until something lies inside it, anything closer is then inside it as well
+        let mut radius = self.particle_index.get_cell_size();
+        for _ in 0..NEAREST_SEARCH_DOUBLINGS {
+            if let Some((id, _)) = nearest(self.particles_within(point, radius)) {
+                return Some(id);
+            }
+            radius *= 2.0;
+        }
+        nearest(self.particles.keys().copied().collect()).map(|(id, _)| id)
+    }
+
+    // Indices of the force fields whose shape contains `point`
+    pub fn fields_containing(&self, point: &DVec3) -> Vec<usize> {
+        self.force_fields.iter().enumerate()
+            .filter(|(_, force_field)| force_field.contains(point))
+            .map(|(index, _)| index)
+            .collect()
     }
 
     pub fn add_force_field(&mut self, force_field: ForceField) {
         self.force_fields.push(force_field);
     }
 
+    // Puts a field back at `index`, the inverse of `remove_force_field`
+    pub fn insert_force_field(&mut self, index: usize, force_field: ForceField) {
+        self.force_fields.insert(index, force_field);
+        self.field_occupancy = self.field_occupancy.iter()
+            .map(|(particle, field)| (*particle, if *field >= index { field + 1 } else { *field }))
+            .collect();
+    }
+
+    pub fn remove_force_field(&mut self, index: usize) -> ForceField {
+        let force_field = self.force_fields.remove(index);
+        // Keep occupancy pointing at the same fields after the indices shift
+        self.field_occupancy = self.field_occupancy.iter()
+            .filter(|(_, field)| *field != index)
+            .map(|(particle, field)| (*particle, if *field > index { field - 1 } else { *field }))
+            .collect();
+        force_field
+    }
+
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) -> usize {
+        self.obstacles.push(obstacle);
+        self.obstacles.len() - 1
+    }
+
+    pub fn insert_obstacle(&mut self, index: usize, obstacle: Obstacle) {
+        self.obstacles.insert(index, obstacle);
+    }
+
+    pub fn remove_obstacle(&mut self, index: usize) -> Obstacle {
+        self.obstacles.remove(index)
+    }
+
+    pub fn get_obstacles(&self) -> &Vec<Obstacle> {
+        &self.obstacles
+    }
+
+    pub fn remove_out_of_bounds(&mut self) {
+        let bounds = match self.bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let exited: Vec<(u64, DVec3)> = self.particles.iter()
+            .filter(|(_, particle)| !bounds.contains(particle.get_pos()))
+            .map(|(id, particle)| (*id, *particle.get_pos()))
+            .collect();
+        for (id, position) in exited {
+            self.emit(SimEvent::BoundaryExit { id, position });
+            self.remove_particle(id);
+        }
+    }
+
     pub fn get_particles(&self) -> &HashMap<u64,Particle> {
         &self.particles
     }
@@ -34,11 +577,19 @@ impl PSim {
     }
 
     pub fn add_forces(&mut self) {
+        let gravitational_constant = self.get_gravitational_constant();
+        let mut events = vec![];
         for (id,particle) in &mut self.particles {
-            for force_field in &self.force_fields {
-                if force_field.affects_particle(particle) {
-                    let force = force_field.calculate_force(particle);
+            for (index, force_field) in self.force_fields.iter().enumerate() {
+                let inside = force_field.affects_particle(particle);
+                if inside {
+                    let force = force_field.calculate_force(particle, &self.softening, gravitational_constant);
                     particle.apply_force(force);
+                    if self.field_occupancy.insert((*id, index)) {
+                        events.push(SimEvent::FieldEntered { particle: *id, field: index });
+                    }
+                } else if self.field_occupancy.remove(&(*id, index)) {
+                    events.push(SimEvent::FieldExited { particle: *id, field: index });
                 }
             }
         }
@@ -50,14 +601,427 @@ impl PSim {
                 let id_i = ids[i];
                 let id_j = ids[j];
                 let (particle_i,particle_j) = self.particles.get_pair_mut(&id_i, &id_j).unwrap();
-                particle_i.interact(particle_j);
+                let contact = match &self.interactions {
+                    Some(interactions) => particle_i.interact_species(particle_j, interactions),
+                    None => particle_i.interact(particle_j, &self.softening, gravitational_constant),
+                };
+                if let Some(contact) = contact {
+                    events.push(SimEvent::Collision { first: id_i, second: id_j, impulse: contact.impulse, contact_point: contact.point });
+                }
+            }
+        }
+        if let Some(sph) = &self.sph {
+            sph.apply(&mut self.particles, self.dimension);
+        }
+        for event in events {
+            self.emit(event);
+        }
+    }
+
+    pub fn reset_forces(&mut self) {
+        for particle in self.particles.values_mut() {
+            particle.reset_forces();
+        }
+    }
+
+    pub fn kinetic_energy(&self) -> f64 {
+        self.particles.values()
+            .filter(|particle| !particle.is_static())
+            .map(|particle| particle.kinetic_energy())
+            .sum()
+    }
+
+    pub fn potential_energy(&self) -> f64 {
+        let gravitational_constant = self.get_gravitational_constant();
+        let particles: Vec<&Particle> = self.particles.values().collect();
+        let mut energy = 0.0;
+        for i in 0..particles.len() {
+            // Species interactions need not be conservative, so they have no pair potential
+            for j in i + 1..particles.len() {
+                if self.interactions.is_none() && particles[i].interacts_with(particles[j]) {
+                    energy += particles[i].potential_energy(particles[j], &self.softening, gravitational_constant);
+                }
+            }
+            // Fields can't move static particles, so their energy there is a constant
+            if particles[i].is_static() {
+                continue;
+            }
+            for force_field in &self.force_fields {
+                if force_field.affects_particle(particles[i]) {
+                    energy += force_field.potential_energy(particles[i], &self.softening, gravitational_constant);
+                }
+            }
+        }
+        energy
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::new(self.kinetic_energy(), self.potential_energy(), self.temperature(), self.thermostat)
+    }
+
+    // Picks the step length for the current forces, never exceeding the requested dt even if that is below min_dt
+    fn choose_dt(&self, dt: f64) -> f64 {
+        let (criterion, min_dt, max_dt) = match self.timestep {
+            Timestep::Fixed => return dt,
+            Timestep::Adaptive { criterion, min_dt, max_dt } => (criterion, min_dt, max_dt),
+        };
+        let mut chosen = dt.min(max_dt);
+        for (id, particle) in &self.particles {
+            if particle.is_static() {
+                continue;
+            }
+            let acceleration = particle.get_acceleration().length();
+            match criterion {
+                TimestepCriterion::Kinematic { eta, courant } => {
+                    let length = if self.softening.get_length() > 0.0 {
+                        self.softening.get_length()
+                    } else {
+                        particle.get_radius()
+                    };
+                    if acceleration > 0.0 {
+                        chosen = chosen.min(eta * (length / acceleration).sqrt());
+                    }
+                    let speed = particle.get_velocity().length();
+                    if speed > 0.0 {
+                        chosen = chosen.min(courant * length / speed);
+                    }
+                }
+                TimestepCriterion::ErrorEstimate { tolerance } => {
+                    let previous = match self.previous_accelerations.get(id) {
+                        Some(previous) => *previous,
+                        None => continue,
+                    };
+                    // Difference between the first order and the trapezoidal position update
+                    let error = 0.5 * self.last_dt * self.last_dt * (particle.get_acceleration() - previous).length();
+                    if error > 0.0 {
+                        const SAFETY_FACTOR: f64 = 0.9;
+                        chosen = chosen.min(SAFETY_FACTOR * self.last_dt * (tolerance / error).cbrt());
+                    }
+                }
+            }
+        }
+        chosen.clamp(min_dt, max_dt.max(min_dt)).min(dt)
+    }
+
+    fn find_impacts(&self, dt: f64) -> Vec<(f64, u64, u64)> {
+        let particles: Vec<(&u64, &Particle)> = self.particles.iter().collect();
+        let mut impacts = vec![];
+        for i in 0..particles.len() {
+            for j in i + 1..particles.len() {
+                let (id_i, particle_i) = particles[i];
+                let (id_j, particle_j) = particles[j];
+                if !self.continuous_collision && !particle_i.get_continuous_collision() && !particle_j.get_continuous_collision() {
+                    continue;
+                }
+                if !particle_i.interacts_with(particle_j) {
+                    continue;
+                }
+                // Same pairs as the discrete check in `Particle::interact` and `Particle::interact_species`
+                let collide = match &self.interactions {
+                    Some(interactions) => interactions.collides(particle_i.get_species(), particle_j.get_species()),
+                    None => !(particle_i.is_fluid() && particle_j.is_fluid()),
+                };
+                if !collide {
+                    continue;
+                }
+                if let Some(time) = particle_i.time_of_impact(particle_j, dt) {
+                    impacts.push((time, *id_i, *id_j));
+                }
             }
         }
+        impacts.sort_by(|a, b| a.0.total_cmp(&b.0));
+        impacts
     }
 
+    // Always covers the whole of `dt`, in equal substeps when the adaptive timestep wants shorter ones.
+    // The caller gathers the forces for the first substep, the later ones gather their own
     pub fn step(&mut self, dt: f64) {
-        for (_, particle) in &mut self.particles {
-            particle.step(dt);
+        let mut remaining = dt;
+        loop {
+            let substeps = (remaining / self.choose_dt(remaining)).ceil();
+            let substeps = if substeps.is_finite() && substeps > 1.0 { substeps } else { 1.0 };
+            let substep = remaining / substeps;
+            self.advance(substep);
+            if substeps == 1.0 {
+                break;
+            }
+            remaining -= substep;
+            self.add_forces();
+        }
+    }
+
+    fn advance(&mut self, dt: f64) {
+        if let Timestep::Adaptive { criterion: TimestepCriterion::ErrorEstimate { .. }, .. } = self.timestep {
+            self.previous_accelerations = self.particles.iter()
+                .map(|(id, particle)| (*id, particle.get_acceleration()))
+                .collect();
+        }
+        if self.dimension == Dimension::Two {
+            for particle in self.particles.values_mut() {
+                particle.flatten();
+            }
+        }
+        self.integrator.apply(&mut self.particles, self.dimension, self.units.boltzmann_constant(), dt, &mut self.rng);
+        if let Some(thermostat) = self.thermostat {
+            thermostat.apply(&mut self.particles, self.dimension, self.units.boltzmann_constant(), dt, &mut self.thermostat_friction, &mut self.rng);
+        }
+
+        // Pairs that would pass through each other are moved to their time of impact, collided there,
+        // and carried through the rest of the step with their new velocities
+        let mut resolved: HashSet<u64> = HashSet::new();
+        let mut events = vec![];
+        for (time, id_i, id_j) in self.find_impacts(dt) {
+            if resolved.contains(&id_i) || resolved.contains(&id_j) {
+                continue;
+            }
+            let (particle_i, particle_j) = self.particles.get_pair_mut(&id_i, &id_j).unwrap();
+            particle_i.update_position(time);
+            particle_j.update_position(time);
+            let contact = particle_i.resolve_collision(particle_j);
+            events.push(SimEvent::Collision { first: id_i, second: id_j, impulse: contact.impulse, contact_point: contact.point });
+            particle_i.update_position(dt - time);
+            particle_j.update_position(dt - time);
+            resolved.insert(id_i);
+            resolved.insert(id_j);
+        }
+
+        for (id, particle) in &mut self.particles {
+            let previous = self.previous_positions.get(id).copied().unwrap_or(*particle.get_pos());
+            if !resolved.contains(id) {
+                particle.update_position(dt);
+            }
+            particle.reset_forces();
+            if particle.is_static() || particle.is_ghost() {
+                continue;
+            }
+            // Obstacles are checked against the whole move so thin walls can't be skipped over
+            for (index, obstacle) in self.obstacles.iter().enumerate() {
+                if let Some(contact) = obstacle.contact(&previous, particle.get_pos(), particle.get_radius()) {
+                    let contact = particle.resolve_obstacle_collision(&contact);
+                    events.push(SimEvent::ObstacleCollision { particle: *id, obstacle: index, impulse: contact.impulse, contact_point: contact.point });
+                }
+            }
+        }
+        // Without obstacles nothing reads them, but stale ones would sweep from long ago once one is added
+        if self.obstacles.is_empty() {
+            self.previous_positions.clear();
+        } else {
+            self.previous_positions = self.particles.iter()
+                .map(|(id, particle)| (*id, *particle.get_pos()))
+                .collect();
+        }
+        for event in events {
+            self.emit(event);
+        }
+        self.last_dt = dt;
+        self.time += dt;
+        self.remove_out_of_bounds();
+        self.rebuild_index();
+    }
+    }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psim::simulator::species::Interaction;
+
+    fn run(simulator: &mut PSim, steps: usize, dt: f64) {
+        for _ in 0..steps {
+            simulator.add_forces();
+            simulator.step(dt);
+            simulator.drain_events();
+        }
+    }
+
+    fn close_pair() -> PSim {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::NBody);
+        simulator.add_particle(Particle::new(DVec3::ZERO, DVec3::ZERO, 100.0, 1.0));
+        simulator.add_particle(Particle::new(DVec3::new(10.0, 0.0, 0.0), DVec3::new(0.0, 3.0, 0.0), 1.0, 1.0));
+        simulator
+    }
+
+    #[test]
+    fn adaptive_step_covers_the_requested_dt_in_substeps() {
+        let mut simulator = close_pair();
+        simulator.set_timestep(Timestep::Adaptive {
+            criterion: TimestepCriterion::Kinematic { eta: 0.2, courant: 0.5 },
+            min_dt: 1e-6,
+            max_dt: 0.01,
+        });
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert!((simulator.get_time() - 0.1).abs() < 1e-12);
+        assert!(simulator.get_last_dt() <= 0.01);
+    }
+
+    #[test]
+    fn adaptive_step_never_exceeds_the_requested_dt() {
+        let mut simulator = close_pair();
+        simulator.set_timestep(Timestep::Adaptive {
+            criterion: TimestepCriterion::Kinematic { eta: 0.2, courant: 0.5 },
+            min_dt: 0.5,
+            max_dt: 1.0,
+        });
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert_eq!(simulator.get_time(), 0.1);
+        assert_eq!(simulator.get_last_dt(), 0.1);
+    }
+
+    #[test]
+    fn wall_added_later_ignores_where_particles_were_before() {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::Custom { gravitational_constant: 0.0 });
+        let id = simulator.add_particle(Particle::new(DVec3::ZERO, DVec3::new(10.0, 0.0, 0.0), 1.0, 1.0));
+        simulator.add_obstacle(Obstacle::Segment { start: DVec3::new(-5.0, -10.0, 0.0), end: DVec3::new(-5.0, 10.0, 0.0) });
+        simulator.step(0.1);
+        simulator.remove_obstacle(0);
+        for _ in 0..100 {
+            simulator.step(0.1);
+        }
+        simulator.add_obstacle(Obstacle::Segment { start: DVec3::new(50.0, -10.0, 0.0), end: DVec3::new(50.0, 10.0, 0.0) });
+        simulator.step(0.1);
+        let particle = &simulator.get_particles()[&id];
+        assert!(particle.get_pos().x > 100.0);
+        assert_eq!(particle.get_velocity().x, 10.0);
+    }
+
+    // A small particle fast enough to cross a thin target within one step
+    fn bullet_and_target(continuous_collision: bool) -> (PSim, u64) {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::Custom { gravitational_constant: 0.0 });
+        simulator.set_continuous_collision(continuous_collision);
+        let bullet = simulator.add_particle(Particle::new(DVec3::ZERO, DVec3::new(1000.0, 0.0, 0.0), 1.0, 0.1));
+        simulator.add_particle(Particle::new(DVec3::new(50.0, 0.0, 0.0), DVec3::ZERO, 1000.0, 1.0));
+        simulator.drain_events();
+        (simulator, bullet)
+    }
+
+    fn collisions(simulator: &mut PSim) -> usize {
+        simulator.drain_events().iter().filter(|event| matches!(event, SimEvent::Collision { .. })).count()
+    }
+
+    #[test]
+    fn fast_particle_tunnels_without_continuous_collision() {
+        let (mut simulator, bullet) = bullet_and_target(false);
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert_eq!(collisions(&mut simulator), 0);
+        assert_eq!(simulator.get_particles()[&bullet].get_pos().x, 100.0);
+    }
+
+    #[test]
+    fn fast_particle_hits_with_continuous_collision() {
+        let (mut simulator, bullet) = bullet_and_target(true);
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert_eq!(collisions(&mut simulator), 1);
+        // Bounced back off the much heavier target
+        let particle = &simulator.get_particles()[&bullet];
+        assert!(particle.get_pos().x < 50.0 && particle.get_velocity().x < 0.0);
+    }
+
+    #[test]
+    fn continuous_collision_respects_species_that_do_not_collide() {
+        let (mut simulator, _) = bullet_and_target(true);
+        simulator.set_interactions(Some(InteractionMatrix::new(1, Interaction::new(0.0, 0.0, false))));
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert_eq!(collisions(&mut simulator), 0);
+    }
+
+    #[test]
+    fn continuous_collision_leaves_fluid_pairs_alone() {
+        let (mut simulator, _) = bullet_and_target(true);
+        for particle in simulator.particles.values_mut() {
+            particle.set_fluid(true);
         }
+        simulator.add_forces();
+        simulator.step(0.1);
+        assert_eq!(collisions(&mut simulator), 0);
+    }
+
+    #[test]
+    fn static_particle_attracts_and_blocks() {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::Custom { gravitational_constant: 1.0 });
+        let anchor = simulator.add_particle(Particle::new_static(DVec3::ZERO, DVec3::ZERO, 1000.0, 5.0));
+        let ball = simulator.add_particle(Particle::new(DVec3::new(20.0, 0.0, 0.0), DVec3::ZERO, 1.0, 1.0));
+        run(&mut simulator, 1000, 0.01);
+        assert_eq!(*simulator.get_particles()[&anchor].get_pos(), DVec3::ZERO);
+        // Pulled in and resting on the surface
+        assert!((simulator.get_particles()[&ball].get_pos().x - 6.0).abs() < 0.01);
     }
-    }
\ No newline at end of file
+
+    #[test]
+    fn ghost_ignores_other_particles() {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::Custom { gravitational_constant: 1.0 });
+        simulator.add_particle(Particle::new_static(DVec3::ZERO, DVec3::ZERO, 1000.0, 5.0));
+        let mut ghost = Particle::new(DVec3::new(20.0, 0.0, 0.0), DVec3::new(-10.0, 0.0, 0.0), 1.0, 1.0);
+        ghost.set_ghost(true);
+        let ghost = simulator.add_particle(ghost);
+        run(&mut simulator, 400, 0.01);
+        assert!((simulator.get_particles()[&ghost].get_pos().x + 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn orbiting_particle_follows_its_ellipse() {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::NBody);
+        let sun = simulator.add_particle(Particle::new(DVec3::new(50.0, 0.0, 0.0), DVec3::new(0.0, 1.0, 0.0), 1000.0, 1.0));
+        let elements = KeplerElements::new(100.0, 0.5, std::f64::consts::FRAC_PI_2, 0.0);
+        let planet = simulator.add_orbiting_particle(sun, &elements, 1e-6, 0.1).unwrap();
+        // Periapsis at a (1 - e) straight "above" the sun, moving with it
+        let particle = &simulator.get_particles()[&planet];
+        assert!(particle.get_pos().distance(DVec3::new(50.0, 50.0, 0.0)) < 1e-9);
+        assert!(particle.get_velocity().x < 0.0);
+        // Apoapsis at a (1 + e) half a period later, T = 2π sqrt(a³ / μ)
+        run(&mut simulator, 9935, 0.01);
+        let distance = simulator.get_particles()[&planet].get_pos().distance(*simulator.get_particles()[&sun].get_pos());
+        assert!((distance - 150.0).abs() < 0.15);
+    }
+
+    #[test]
+    fn nearest_particle_of_nothing_is_none() {
+        assert_eq!(PSim::new().nearest_particle(&DVec3::new(50.0, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn nearest_particle_is_found_far_from_everything() {
+        let mut simulator = PSim::new();
+        for i in 0..100 {
+            simulator.add_particle(Particle::new(DVec3::new((i % 10) as f64, (i / 10) as f64, 0.0), DVec3::ZERO, 1.0, 0.1));
+        }
+        let nearest = simulator.nearest_particle(&DVec3::new(50.0, 0.0, 0.0)).unwrap();
+        assert_eq!(*simulator.get_particles()[&nearest].get_pos(), DVec3::new(9.0, 0.0, 0.0));
+        assert_eq!(simulator.particles_in_rectangle(&DVec3::splat(-1e12), &DVec3::splat(1e12)).len(), 100);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn loaded_snapshot_answers_spatial_queries() {
+        let mut simulator = PSim::new();
+        for i in 0..10 {
+            simulator.add_particle(Particle::new(DVec3::new(i as f64 * 10.0, 0.0, 0.0), DVec3::ZERO, 1.0, 1.0));
+        }
+        let json = serde_json::to_string(&simulator).unwrap();
+        let loaded: PSim = serde_json::from_str(&json).unwrap();
+        let point = DVec3::new(30.0, 0.0, 0.0);
+        assert_eq!(loaded.particles_at(&point).len(), 1);
+        assert_eq!(loaded.particles_within(&point, 15.0).len(), 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unbounded_sides_survive_json() {
+        let mut simulator = PSim::new();
+        simulator.set_bounds(Some(Bounds::new(DVec3::new(0.0, 0.0, f64::NEG_INFINITY), DVec3::new(10.0, 20.0, f64::INFINITY))));
+        let json = serde_json::to_string(&simulator).unwrap();
+        let loaded: PSim = serde_json::from_str(&json).unwrap();
+        let bounds = loaded.get_bounds().unwrap();
+        assert_eq!(bounds.min, DVec3::new(0.0, 0.0, f64::NEG_INFINITY));
+        assert_eq!(bounds.max, DVec3::new(10.0, 20.0, f64::INFINITY));
+    }
+}
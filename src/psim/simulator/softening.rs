@@ -0,0 +1,86 @@
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Softening {
+    None,
+    Plummer { length: f64 },
+    Spline { length: f64 },
+}
+
+impl Softening {
+    pub fn get_length(&self) -> f64 {
+        match self {
+            Softening::None => 0.0,
+            Softening::Plummer { length } => *length,
+            Softening::Spline { length } => *length,
+        }
+    }
+
+    // Force magnitude per unit G*m1*m2 at the given separation
+    pub fn force_factor(&self, distance: f64) -> f64 {
+        match *self {
+            Softening::None => {
+                if distance > 0.0 {
+                    1.0 / (distance * distance)
+                } else {
+                    0.0
+                }
+            }
+            Softening::Plummer { length } => {
+                let softened = distance * distance + length * length;
+                if softened > 0.0 {
+                    distance / (softened * softened.sqrt())
+                } else {
+                    0.0
+                }
+            }
+            Softening::Spline { length } => {
+                // Monaghan & Lattanzio cubic spline, exactly Newtonian beyond `length`
+                if distance >= length {
+                    return Softening::None.force_factor(distance);
+                }
+                let u = distance / length;
+                let h3 = length * length * length;
+                let kernel = if u < 0.5 {
+                    10.666666666667 + u * u * (32.0 * u - 38.4)
+                } else {
+                    21.333333333333 - 48.0 * u + 38.4 * u * u - 10.666666666667 * u * u * u - 0.066666666667 / (u * u * u)
+                };
+                distance * kernel / h3
+            }
+        }
+    }
+
+    // Potential energy per unit G*m1*m2 at the given separation
+    pub fn potential_factor(&self, distance: f64) -> f64 {
+        match *self {
+            Softening::None => {
+                if distance > 0.0 {
+                    -1.0 / distance
+                } else {
+                    0.0
+                }
+            }
+            Softening::Plummer { length } => {
+                let softened = distance * distance + length * length;
+                if softened > 0.0 {
+                    -1.0 / softened.sqrt()
+                } else {
+                    0.0
+                }
+            }
+            Softening::Spline { length } => {
+                if distance >= length {
+                    return Softening::None.potential_factor(distance);
+                }
+                let u = distance / length;
+                let kernel = if u < 0.5 {
+                    -2.8 + u * u * (5.333333333333 + u * u * (6.4 * u - 9.6))
+                } else {
+                    -3.2 + 0.066666666667 / u + u * u * (10.666666666667 + u * (-16.0 + u * (9.6 - 2.133333333333 * u)))
+                };
+                kernel / length
+            }
+        }
+    }
+}
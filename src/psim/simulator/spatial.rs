@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use glam::DVec3;
+
+type Cell = (i64, i64, i64);
+
+// Uniform hash grid, items are bucketed by the cell their position falls in
+#[derive(Clone)]
+pub struct SpatialGrid<T> {
+    cell_size: f64,
+    cells: HashMap<Cell, Vec<(T, DVec3)>>,
+    // Lowest and highest cell on each axis that held an item since the last clear, queries never look past them
+    occupied: Option<(Cell, Cell)>,
+}
+
+impl<T: Copy> Default for SpatialGrid<T> {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl<T: Copy> SpatialGrid<T> {
+    pub fn new(cell_size: f64) -> Self {
+        SpatialGrid { cell_size, cells: HashMap::new(), occupied: None }
+    }
+
+    fn cell(&self, position: &DVec3) -> Cell {
+        let cell = (*position / self.cell_size).floor();
+        (cell.x as i64, cell.y as i64, cell.z as i64)
+    }
+
+    pub fn get_cell_size(&self) -> f64 {
+        self.cell_size
+    }
+
+    pub fn insert(&mut self, item: T, position: DVec3) {
+        let cell = self.cell(&position);
+        self.occupied = Some(match self.occupied {
+            Some((low, high)) => ((low.0.min(cell.0), low.1.min(cell.1), low.2.min(cell.2)), (high.0.max(cell.0), high.1.max(cell.1), high.2.max(cell.2))),
+            None => (cell, cell),
+        });
+        self.cells.entry(cell).or_default().push((item, position));
+    }
+
+    // Drops one entry of `item` from the cell of `position`
+    pub fn remove(&mut self, item: T, position: &DVec3) where T: PartialEq {
+        let cell = self.cell(position);
+        if let Some(items) = self.cells.get_mut(&cell) {
+            if let Some(index) = items.iter().position(|(other, _)| *other == item) {
+                items.swap_remove(index);
+            }
+            if items.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.occupied = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.values().map(|items| items.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    // Buckets of the cells from `min` to `max`, cut down to the occupied ones. Planar items all share one
+    // z cell, so the z range collapses to it. A range still larger than the number of buckets walks the buckets instead
+    fn cells_between(&self, min: Cell, max: Cell) -> Vec<&Vec<(T, DVec3)>> {
+        let (low, high) = match self.occupied {
+            Some((low, high)) => (
+                (min.0.max(low.0), min.1.max(low.1), min.2.max(low.2)),
+                (max.0.min(high.0), max.1.min(high.1), max.2.min(high.2)),
+            ),
+            None => return vec![],
+        };
+        if low.0 > high.0 || low.1 > high.1 || low.2 > high.2 {
+            return vec![];
+        }
+        let span = |low: i64, high: i64| (high as i128 - low as i128 + 1) as u128;
+        let count = span(low.0, high.0).saturating_mul(span(low.1, high.1)).saturating_mul(span(low.2, high.2));
+        if count > self.cells.len() as u128 {
+            let inside = |cell: &Cell| (low.0..=high.0).contains(&cell.0) && (low.1..=high.1).contains(&cell.1) && (low.2..=high.2).contains(&cell.2);
+            return self.cells.iter().filter(|(cell, _)| inside(cell)).map(|(_, items)| items).collect();
+        }
+        let mut found = vec![];
+        for x in low.0..=high.0 {
+            for y in low.1..=high.1 {
+                for z in low.2..=high.2 {
+                    if let Some(items) = self.cells.get(&(x, y, z)) {
+                        found.push(items);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    // Items within `radius` of `position`, with their distance to it
+    pub fn neighbours(&self, position: &DVec3, radius: f64) -> Vec<(T, f64)> {
+        let min = self.cell(&(*position - DVec3::splat(radius)));
+        let max = self.cell(&(*position + DVec3::splat(radius)));
+        let mut found = vec![];
+        for items in self.cells_between(min, max) {
+            for (item, item_position) in items {
+                let distance = item_position.distance(*position);
+                if distance <= radius {
+                    found.push((*item, distance));
+                }
+            }
+        }
+        found
+    }
+
+    // Items with positions inside the axis-aligned box from `min` to `max`
+    pub fn within_box(&self, min: &DVec3, max: &DVec3) -> Vec<T> {
+        let mut found = vec![];
+        for items in self.cells_between(self.cell(min), self.cell(max)) {
+            for (item, position) in items {
+                if position.cmpge(*min).all() && position.cmple(*max).all() {
+                    found.push(*item);
+                }
+            }
+        }
+        found
+    }
+}
+
+// Even-odd test in the xy plane, the polygon is closed from its last point back to its first
+pub fn polygon_contains(points: &[DVec3], point: &DVec3) -> bool {
+    let mut inside = false;
+    for (index, start) in points.iter().enumerate() {
+        let end = &points[(index + 1) % points.len()];
+        if (start.y > point.y) != (end.y > point.y) {
+            let crossing = start.x + (point.y - start.y) / (end.y - start.y) * (end.x - start.x);
+            if point.x < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planar_grid() -> SpatialGrid<usize> {
+        let mut grid = SpatialGrid::new(1.0);
+        for index in 0..100 {
+            grid.insert(index, DVec3::new((index % 10) as f64, (index / 10) as f64, 0.0));
+        }
+        grid
+    }
+
+    #[test]
+    fn empty_grid_answers_huge_queries() {
+        let grid: SpatialGrid<usize> = SpatialGrid::new(1.0);
+        assert!(grid.neighbours(&DVec3::ZERO, 1e12).is_empty());
+        assert!(grid.within_box(&DVec3::splat(-1e12), &DVec3::splat(1e12)).is_empty());
+    }
+
+    #[test]
+    fn huge_queries_only_visit_occupied_cells() {
+        let grid = planar_grid();
+        assert_eq!(grid.neighbours(&DVec3::new(50.0, 0.0, 0.0), 1e12).len(), 100);
+        assert_eq!(grid.within_box(&DVec3::splat(-1e12), &DVec3::splat(1e12)).len(), 100);
+        assert_eq!(grid.within_box(&DVec3::new(-1e12, -1e12, -1e12), &DVec3::new(4.5, 1e12, 1e12)).len(), 50);
+    }
+
+    #[test]
+    fn queries_outside_the_occupied_cells_find_nothing() {
+        let grid = planar_grid();
+        assert!(grid.neighbours(&DVec3::new(50.0, 50.0, 0.0), 10.0).is_empty());
+        assert!(grid.within_box(&DVec3::new(0.0, 0.0, 1.0), &DVec3::new(9.0, 9.0, 1e12)).is_empty());
+    }
+
+    #[test]
+    fn removed_items_are_not_found() {
+        let mut grid = planar_grid();
+        grid.remove(11, &DVec3::new(1.0, 1.0, 0.0));
+        assert_eq!(grid.len(), 99);
+        assert!(grid.neighbours(&DVec3::new(1.0, 1.0, 0.0), 0.5).is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+// Inside this fraction of the range every pair repels, regardless of the sign of its strength
+const REPULSION_RADIUS_FRACTION: f64 = 0.3;
+
+// How one species reacts to another, a positive strength attracts and a negative one repels
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interaction {
+    pub strength: f64,
+    pub range: f64,
+    pub collide: bool,
+}
+
+impl Interaction {
+    pub const NONE: Interaction = Interaction { strength: 0.0, range: 0.0, collide: false };
+
+    pub fn new(strength: f64, range: f64, collide: bool) -> Self {
+        Interaction { strength, range, collide }
+    }
+
+    // Acceleration towards the other particle, the piecewise linear "particle life" profile
+    pub fn acceleration(&self, distance: f64) -> f64 {
+        if distance >= self.range || self.range <= 0.0 {
+            return 0.0;
+        }
+        let repulsion_radius = REPULSION_RADIUS_FRACTION * self.range;
+        if distance < repulsion_radius {
+            self.strength.abs() * (distance / repulsion_radius - 1.0)
+        } else {
+            let peak = 0.5 * (self.range + repulsion_radius);
+            self.strength * (1.0 - (distance - peak).abs() / (peak - repulsion_radius))
+        }
+    }
+}
+
+// Row is the species feeling the force, column the species exerting it, so the matrix need not be symmetric
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawInteractionMatrix"))]
+pub struct InteractionMatrix {
+    species_count: usize,
+    interactions: Vec<Interaction>,
+}
+
+// What a file holds before it is checked to be a full square matrix
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawInteractionMatrix {
+    species_count: usize,
+    interactions: Vec<Interaction>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawInteractionMatrix> for InteractionMatrix {
+    type Error = String;
+
+    fn try_from(raw: RawInteractionMatrix) -> Result<Self, Self::Error> {
+        if raw.species_count.checked_mul(raw.species_count) != Some(raw.interactions.len()) {
+            return Err(format!(
+                "{} species need {} interactions, found {}",
+                raw.species_count, raw.species_count.saturating_mul(raw.species_count), raw.interactions.len(),
+            ));
+        }
+        Ok(InteractionMatrix { species_count: raw.species_count, interactions: raw.interactions })
+    }
+}
+
+impl InteractionMatrix {
+    pub fn new(species_count: usize, interaction: Interaction) -> Self {
+        InteractionMatrix { species_count, interactions: vec![interaction; species_count * species_count] }
+    }
+
+    pub fn get_species_count(&self) -> usize {
+        self.species_count
+    }
+
+    // Species outside the matrix don't interact at all
+    pub fn get(&self, species: usize, other: usize) -> Interaction {
+        if species >= self.species_count || other >= self.species_count {
+            return Interaction::NONE;
+        }
+        self.interactions[species * self.species_count + other]
+    }
+
+    pub fn set(&mut self, species: usize, other: usize, interaction: Interaction) {
+        if species < self.species_count && other < self.species_count {
+            self.interactions[species * self.species_count + other] = interaction;
+        }
+    }
+
+    // A pair collides when either direction of the matrix asks for it
+    pub fn collides(&self, species: usize, other: usize) -> bool {
+        self.get(species, other).collide || self.get(other, species).collide
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn species_outside_the_matrix_do_not_interact() {
+        let matrix = InteractionMatrix::new(2, Interaction::new(1.0, 10.0, true));
+        assert!(matrix.collides(0, 1));
+        assert!(!matrix.collides(0, 2));
+        assert_eq!(matrix.get(2, 0).range, 0.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn matrix_survives_json() {
+        let mut matrix = InteractionMatrix::new(2, Interaction::NONE);
+        matrix.set(0, 1, Interaction::new(-1.0, 5.0, true));
+        let json = serde_json::to_string(&matrix).unwrap();
+        let loaded: InteractionMatrix = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.get_species_count(), 2);
+        assert_eq!(loaded.get(0, 1).strength, -1.0);
+        assert!(loaded.collides(1, 0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn incomplete_matrix_is_rejected() {
+        assert!(serde_json::from_str::<InteractionMatrix>(r#"{"species_count":3,"interactions":[]}"#).is_err());
+    }
+}
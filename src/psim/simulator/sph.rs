@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use glam::DVec3;
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::spatial::SpatialGrid;
+
+// Standard Monaghan artificial viscosity coefficients
+const DEFAULT_VISCOSITY_ALPHA: f64 = 1.0;
+const DEFAULT_VISCOSITY_BETA: f64 = 2.0;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EquationOfState {
+    // p = c² (ρ - ρ0)
+    Isothermal { sound_speed: f64 },
+    // p = ρ0 c² / γ ((ρ / ρ0)^γ - 1), the weakly compressible liquid of Monaghan 1994
+    Tait { sound_speed: f64, gamma: f64 },
+}
+
+impl EquationOfState {
+    pub fn pressure(&self, density: f64, rest_density: f64) -> f64 {
+        let pressure = match self {
+            EquationOfState::Isothermal { sound_speed } => sound_speed * sound_speed * (density - rest_density),
+            EquationOfState::Tait { sound_speed, gamma } => {
+                rest_density * sound_speed * sound_speed / gamma * ((density / rest_density).powf(*gamma) - 1.0)
+            }
+        };
+        // Under-dense particles at a free surface would otherwise pull on each other and clump
+        pressure.max(0.0)
+    }
+
+    pub fn get_sound_speed(&self) -> f64 {
+        match self {
+            EquationOfState::Isothermal { sound_speed } | EquationOfState::Tait { sound_speed, .. } => *sound_speed,
+        }
+    }
+}
+
+// Cubic spline kernel with support 2h, normalised for the simulated dimension
+fn kernel(distance: f64, smoothing_length: f64, dimension: Dimension) -> f64 {
+    let q = distance / smoothing_length;
+    let value = if q < 1.0 {
+        1.0 - 1.5 * q * q + 0.75 * q * q * q
+    } else if q < 2.0 {
+        0.25 * (2.0 - q).powi(3)
+    } else {
+        0.0
+    };
+    kernel_normalisation(smoothing_length, dimension) * value
+}
+
+fn kernel_derivative(distance: f64, smoothing_length: f64, dimension: Dimension) -> f64 {
+    let q = distance / smoothing_length;
+    let value = if q < 1.0 {
+        -3.0 * q + 2.25 * q * q
+    } else if q < 2.0 {
+        -0.75 * (2.0 - q).powi(2)
+    } else {
+        0.0
+    };
+    kernel_normalisation(smoothing_length, dimension) * value / smoothing_length
+}
+
+fn kernel_normalisation(smoothing_length: f64, dimension: Dimension) -> f64 {
+    match dimension {
+        Dimension::Two => 10.0 / (7.0 * PI * smoothing_length * smoothing_length),
+        Dimension::Three => 1.0 / (PI * smoothing_length.powi(3)),
+    }
+}
+
+// Only particles marked as fluid take part, everything else is left to gravity and collisions
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sph {
+    smoothing_length: f64,
+    rest_density: f64,
+    equation_of_state: EquationOfState,
+    viscosity_alpha: f64,
+    viscosity_beta: f64,
+}
+
+struct FluidParticle {
+    id: u64,
+    position: DVec3,
+    velocity: DVec3,
+    mass: f64,
+    density: f64,
+    pressure: f64,
+}
+
+impl Sph {
+    pub fn new(smoothing_length: f64, rest_density: f64, equation_of_state: EquationOfState) -> Self {
+        Sph {
+            smoothing_length,
+            rest_density,
+            equation_of_state,
+            viscosity_alpha: DEFAULT_VISCOSITY_ALPHA,
+            viscosity_beta: DEFAULT_VISCOSITY_BETA,
+        }
+    }
+
+    pub fn get_smoothing_length(&self) -> f64 {
+        self.smoothing_length
+    }
+
+    pub fn set_smoothing_length(&mut self, smoothing_length: f64) {
+        self.smoothing_length = smoothing_length;
+    }
+
+    pub fn get_rest_density(&self) -> f64 {
+        self.rest_density
+    }
+
+    pub fn set_rest_density(&mut self, rest_density: f64) {
+        self.rest_density = rest_density;
+    }
+
+    pub fn get_equation_of_state(&self) -> &EquationOfState {
+        &self.equation_of_state
+    }
+
+    pub fn set_equation_of_state(&mut self, equation_of_state: EquationOfState) {
+        self.equation_of_state = equation_of_state;
+    }
+
+    pub fn get_viscosity(&self) -> (f64, f64) {
+        (self.viscosity_alpha, self.viscosity_beta)
+    }
+
+    pub fn set_viscosity(&mut self, alpha: f64, beta: f64) {
+        self.viscosity_alpha = alpha;
+        self.viscosity_beta = beta;
+    }
+
+    // Artificial viscosity term Π_ij, only acts on approaching pairs
+    fn viscosity(&self, first: &FluidParticle, second: &FluidParticle, distance: f64) -> f64 {
+        let relative_position = first.position - second.position;
+        let relative_velocity = first.velocity - second.velocity;
+        let approach = relative_velocity.dot(relative_position);
+        if approach >= 0.0 {
+            return 0.0;
+        }
+        let h = self.smoothing_length;
+        let mu = h * approach / (distance * distance + 0.01 * h * h);
+        let mean_density = 0.5 * (first.density + second.density);
+        let sound_speed = self.equation_of_state.get_sound_speed();
+        (-self.viscosity_alpha * sound_speed * mu + self.viscosity_beta * mu * mu) / mean_density
+    }
+
+    // Updates density and pressure of every fluid particle and applies the pressure and viscosity forces
+    pub fn apply(&self, particles: &mut HashMap<u64, Particle>, dimension: Dimension) {
+        let h = self.smoothing_length;
+        let support = 2.0 * h;
+        let mut fluid: Vec<FluidParticle> = particles.iter()
+            .filter(|(_, particle)| particle.is_fluid() && !particle.is_ghost())
+            .map(|(id, particle)| FluidParticle {
+                id: *id,
+                position: *particle.get_pos(),
+                velocity: *particle.get_velocity(),
+                mass: particle.get_mass(),
+                density: 0.0,
+                pressure: 0.0,
+            })
+            .collect();
+
+        let mut grid = SpatialGrid::new(support);
+        for (index, particle) in fluid.iter().enumerate() {
+            grid.insert(index, particle.position);
+        }
+        let neighbours: Vec<Vec<(usize, f64)>> = fluid.iter()
+            .map(|particle| grid.neighbours(&particle.position, support))
+            .collect();
+
+        // Summation density, the particle itself is among its neighbours
+        for index in 0..fluid.len() {
+            let density: f64 = neighbours[index].iter()
+                .map(|&(other, distance)| fluid[other].mass * kernel(distance, h, dimension))
+                .sum();
+            fluid[index].density = density;
+            fluid[index].pressure = self.equation_of_state.pressure(density, self.rest_density);
+        }
+
+        for (index, particle) in fluid.iter().enumerate() {
+            let mut acceleration = DVec3::ZERO;
+            for &(other_index, distance) in &neighbours[index] {
+                if other_index == index || distance == 0.0 {
+                    continue;
+                }
+                let other = &fluid[other_index];
+                let gradient = (particle.position - other.position) / distance * kernel_derivative(distance, h, dimension);
+                let pressure_term = particle.pressure / (particle.density * particle.density)
+                    + other.pressure / (other.density * other.density);
+                acceleration -= other.mass * (pressure_term + self.viscosity(particle, other, distance)) * gradient;
+            }
+
+            let target = particles.get_mut(&particle.id).unwrap();
+            target.set_density(particle.density);
+            target.set_pressure(particle.pressure);
+            target.apply_force(acceleration * particle.mass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMOOTHING_LENGTH: f64 = 1.5;
+
+    // ∫ W dV over the support, with the shell of the simulated dimension
+    fn integral(dimension: Dimension) -> f64 {
+        let steps = 100_000;
+        let dr = 2.0 * SMOOTHING_LENGTH / steps as f64;
+        (0..steps).map(|step| {
+            let r = (step as f64 + 0.5) * dr;
+            let shell = match dimension {
+                Dimension::Two => 2.0 * PI * r,
+                Dimension::Three => 4.0 * PI * r * r,
+            };
+            kernel(r, SMOOTHING_LENGTH, dimension) * shell * dr
+        }).sum()
+    }
+
+    #[test]
+    fn kernel_integrates_to_one() {
+        assert!((integral(Dimension::Two) - 1.0).abs() < 1e-6);
+        assert!((integral(Dimension::Three) - 1.0).abs() < 1e-6);
+        assert_eq!(kernel(2.0 * SMOOTHING_LENGTH, SMOOTHING_LENGTH, Dimension::Two), 0.0);
+    }
+
+    #[test]
+    fn kernel_derivative_matches_the_kernel() {
+        let step = 1e-6;
+        for distance in [0.3, 1.0, 1.6, 2.5] {
+            let slope = (kernel(distance + step, SMOOTHING_LENGTH, Dimension::Two) - kernel(distance - step, SMOOTHING_LENGTH, Dimension::Two)) / (2.0 * step);
+            assert!((slope - kernel_derivative(distance, SMOOTHING_LENGTH, Dimension::Two)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn pressure_vanishes_at_rest_density_and_below() {
+        let isothermal = EquationOfState::Isothermal { sound_speed: 2.0 };
+        let tait = EquationOfState::Tait { sound_speed: 2.0, gamma: 7.0 };
+        for equation_of_state in [isothermal, tait] {
+            assert_eq!(equation_of_state.pressure(1.0, 1.0), 0.0);
+            assert_eq!(equation_of_state.pressure(0.5, 1.0), 0.0);
+            assert!(equation_of_state.pressure(1.1, 1.0) > 0.0);
+        }
+        assert!((isothermal.pressure(1.5, 1.0) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn particle_inside_a_uniform_fluid_at_rest_feels_no_force() {
+        let side = 11;
+        let mut particles = HashMap::new();
+        for index in 0..side * side {
+            let position = DVec3::new((index % side) as f64, (index / side) as f64, 0.0);
+            let mut particle = Particle::new(position, DVec3::ZERO, 1.0, 0.5);
+            particle.set_fluid(true);
+            particles.insert(index as u64, particle);
+        }
+        // Rest density below the lattice's, so every particle is under pressure
+        let sph = Sph::new(SMOOTHING_LENGTH, 0.5, EquationOfState::Isothermal { sound_speed: 10.0 });
+        sph.apply(&mut particles, Dimension::Two);
+
+        let center = &particles[&((side * side / 2) as u64)];
+        assert!(center.get_pressure() > 0.0);
+        assert!(center.get_total_forces().length() < 1e-9);
+        // The edges are pushed outwards
+        assert!(particles[&0].get_total_forces().x < 0.0);
+    }
+}
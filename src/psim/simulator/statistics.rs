@@ -0,0 +1,362 @@
+use std::f64::consts::PI;
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::events::SimEvent;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::psim::PSim;
+
+// Equal width bins from `min`. `expected` holds the same bins under the reference distribution, if there is one
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    min: f64,
+    bin_width: f64,
+    values: Vec<f64>,
+    expected: Option<Vec<f64>>,
+}
+
+impl Histogram {
+    pub fn get_min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn get_bin_width(&self) -> f64 {
+        self.bin_width
+    }
+
+    pub fn get_values(&self) -> &Vec<f64> {
+        &self.values
+    }
+
+    pub fn get_expected(&self) -> Option<&Vec<f64>> {
+        self.expected.as_ref()
+    }
+
+    pub fn get_centers(&self) -> Vec<f64> {
+        (0..self.values.len()).map(|bin| self.min + (bin as f64 + 0.5) * self.bin_width).collect()
+    }
+}
+
+// Collisions between particles over some stretch of time, with kinetic theory's prediction for comparison
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CollisionStatistics {
+    collisions: usize,
+    // Collisions per particle per unit time
+    frequency: f64,
+    mean_free_path: Option<f64>,
+    predicted_mean_free_path: Option<f64>,
+    // Momentum carried across by the collisions, to add to the virial pressure of hard spheres
+    pressure: f64,
+}
+
+impl CollisionStatistics {
+    pub fn get_collisions(&self) -> usize {
+        self.collisions
+    }
+
+    pub fn get_frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn get_mean_free_path(&self) -> Option<f64> {
+        self.mean_free_path
+    }
+
+    pub fn get_predicted_mean_free_path(&self) -> Option<f64> {
+        self.predicted_mean_free_path
+    }
+
+    pub fn get_pressure(&self) -> f64 {
+        self.pressure
+    }
+}
+
+// Abramowitz & Stegun 7.1.26, good to about 1e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+// Fraction of particles with less than `energy` kinetic energy, in units of k_B T, in thermal equilibrium
+fn boltzmann_cdf(energy: f64, dimension: Dimension) -> f64 {
+    match dimension {
+        Dimension::Two => 1.0 - (-energy).exp(),
+        Dimension::Three => erf(energy.sqrt()) - 2.0 * (energy / PI).sqrt() * (-energy).exp(),
+    }
+}
+
+// Area in 2D, from the bounds when they are finite and otherwise the box around the particles
+pub fn volume(simulator: &PSim) -> Option<f64> {
+    let axes = simulator.get_dimension().count();
+    let extent = match simulator.get_bounds() {
+        Some(bounds) if (0..axes).all(|axis| (bounds.max - bounds.min)[axis].is_finite()) => bounds.max - bounds.min,
+        _ => {
+            let mut positions = simulator.get_particles().values().map(|particle| *particle.get_pos());
+            let first = positions.next()?;
+            let (min, max) = positions.fold((first, first), |(min, max), position| (min.min(position), max.max(position)));
+            max - min
+        }
+    };
+    let volume: f64 = (0..axes).map(|axis| extent[axis]).product();
+    if volume > 0.0 { Some(volume) } else { None }
+}
+
+fn moving_particles(simulator: &PSim) -> Vec<&Particle> {
+    simulator.get_particles().values().filter(|particle| !particle.is_static()).collect()
+}
+
+// Histogram of `measure` over the moving particles, expected counts come from each particle's own
+// Maxwell–Boltzmann distribution at the measured temperature, `energy` turning a value into its kinetic energy
+fn maxwell_boltzmann_histogram(
+    simulator: &PSim,
+    bins: usize,
+    measure: impl Fn(&Particle) -> f64,
+    energy: impl Fn(&Particle, f64) -> f64,
+) -> Option<Histogram> {
+    let particles = moving_particles(simulator);
+    let max = particles.iter().map(|particle| measure(particle)).fold(0.0, f64::max);
+    if bins == 0 || max <= 0.0 {
+        return None;
+    }
+    let bin_width = max / bins as f64;
+    let mut values = vec![0.0; bins];
+    for particle in &particles {
+        // The largest value belongs in the last bin
+        let bin = ((measure(particle) / bin_width) as usize).min(bins - 1);
+        values[bin] += 1.0;
+    }
+
+    let thermal_energy = simulator.temperature() * simulator.get_units().boltzmann_constant();
+    let dimension = simulator.get_dimension();
+    let expected = if thermal_energy > 0.0 {
+        Some((0..bins).map(|bin| {
+            let (low, high) = (bin as f64 * bin_width, (bin + 1) as f64 * bin_width);
+            particles.iter()
+                .map(|particle| boltzmann_cdf(energy(particle, high) / thermal_energy, dimension) - boltzmann_cdf(energy(particle, low) / thermal_energy, dimension))
+                .sum()
+        }).collect())
+    } else {
+        None
+    };
+    Some(Histogram { min: 0.0, bin_width, values, expected })
+}
+
+pub fn speed_histogram(simulator: &PSim, bins: usize) -> Option<Histogram> {
+    maxwell_boltzmann_histogram(
+        simulator,
+        bins,
+        |particle| particle.get_velocity().length(),
+        |particle, speed| 0.5 * particle.get_mass() * speed * speed,
+    )
+}
+
+pub fn energy_histogram(simulator: &PSim, bins: usize) -> Option<Histogram> {
+    maxwell_boltzmann_histogram(simulator, bins, |particle| particle.kinetic_energy(), |_, energy| energy)
+}
+
+// g(r) out to `max_radius`, 1 everywhere for an ideal gas. Particles near the edge have part of
+// their shells outside, which pulls the larger radii below 1 unless `max_radius` is small next to the box
+pub fn radial_distribution(simulator: &PSim, max_radius: f64, bins: usize) -> Option<Histogram> {
+    let particles = simulator.get_particles();
+    let volume = volume(simulator)?;
+    if bins == 0 || max_radius <= 0.0 || particles.len() < 2 {
+        return None;
+    }
+    let bin_width = max_radius / bins as f64;
+    let mut values = vec![0.0; bins];
+    for (id, particle) in particles {
+        for other in simulator.particles_within(particle.get_pos(), max_radius) {
+            if other == *id {
+                continue;
+            }
+            let distance = particle.get_pos().distance(*particles[&other].get_pos());
+            if distance < max_radius {
+                values[((distance / bin_width) as usize).min(bins - 1)] += 1.0;
+            }
+        }
+    }
+    let count = particles.len() as f64;
+    let density = count / volume;
+    for (bin, value) in values.iter_mut().enumerate() {
+        let (inner, outer) = (bin as f64 * bin_width, (bin + 1) as f64 * bin_width);
+        let shell = match simulator.get_dimension() {
+            Dimension::Two => PI * (outer.powi(2) - inner.powi(2)),
+            Dimension::Three => 4.0 / 3.0 * PI * (outer.powi(3) - inner.powi(3)),
+        };
+        *value /= count * density * shell;
+    }
+    Some(Histogram { min: 0.0, bin_width, values, expected: None })
+}
+
+// P = (N k_B T + Σ r_ij · F_ij / d) / V over the pair forces of the current configuration, force per length in 2D.
+// Collisions only exchange impulses, so hard spheres need the pressure from `collision_statistics` on top
+pub fn virial_pressure(simulator: &PSim) -> Option<f64> {
+    let volume = volume(simulator)?;
+    let dimension = simulator.get_dimension().count() as f64;
+    let gravitational_constant = simulator.get_gravitational_constant();
+    let particles: Vec<&Particle> = simulator.get_particles().values().collect();
+    let mut virial = 0.0;
+    for i in 0..particles.len() {
+        for j in i + 1..particles.len() {
+            if !particles[i].interacts_with(particles[j]) {
+                continue;
+            }
+            // Forces measured on copies, the collision they may resolve on the way doesn't matter here
+            let (mut first, mut second) = (*particles[i], *particles[j]);
+            first.reset_forces();
+            second.reset_forces();
+            match simulator.get_interactions() {
+                Some(interactions) => first.interact_species(&mut second, interactions),
+                None => first.interact(&mut second, simulator.get_softening(), gravitational_constant),
+            };
+            // Only the part that is equal and opposite, species forces needn't be
+            let separation = *first.get_pos() - *second.get_pos();
+            virial += separation.dot(*first.get_total_forces() - *second.get_total_forces()) / 2.0;
+        }
+    }
+    Some((2.0 * simulator.kinetic_energy() + virial) / (dimension * volume))
+}
+
+// From the collision events of `duration` worth of steps, ending at the current state
+pub fn collision_statistics(simulator: &PSim, events: &[SimEvent], duration: f64) -> Option<CollisionStatistics> {
+    let particles = moving_particles(simulator);
+    let volume = volume(simulator)?;
+    if particles.is_empty() || duration <= 0.0 {
+        return None;
+    }
+    let mut collisions = 0;
+    let mut virial = 0.0;
+    for event in events {
+        if let SimEvent::Collision { first, second, impulse, .. } = event {
+            collisions += 1;
+            // Along the line of centres, pushing them apart, at the distance they touch
+            if let (Some(first), Some(second)) = (simulator.get_particles().get(first), simulator.get_particles().get(second)) {
+                virial += (first.get_radius() + second.get_radius()) * impulse.length();
+            }
+        }
+    }
+    let count = particles.len() as f64;
+    let frequency = 2.0 * collisions as f64 / (count * duration);
+    let mean_speed = particles.iter().map(|particle| particle.get_velocity().length()).sum::<f64>() / count;
+    // Dilute gas λ = 1 / (√2 n σ), centres pass within a diameter of each other so σ is twice that in 2D
+    let diameter = 2.0 * particles.iter().map(|particle| particle.get_radius()).sum::<f64>() / count;
+    let cross_section = match simulator.get_dimension() {
+        Dimension::Two => 2.0 * diameter,
+        Dimension::Three => PI * diameter * diameter,
+    };
+    let dimension = simulator.get_dimension().count() as f64;
+    Some(CollisionStatistics {
+        collisions,
+        frequency,
+        mean_free_path: if frequency > 0.0 { Some(mean_speed / frequency) } else { None },
+        predicted_mean_free_path: if cross_section > 0.0 { Some(volume / (2.0_f64.sqrt() * count * cross_section)) } else { None },
+        pressure: virial / (dimension * volume * duration),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+
+    use crate::psim::simulator::psim::Bounds;
+    use crate::psim::simulator::units::UnitSystem;
+
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    // Square lattice with `spacing` between neighbours, at rest unless given speeds, without gravity
+    fn lattice(side: usize, spacing: f64, velocity: impl Fn(usize) -> DVec3) -> PSim {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::Custom { gravitational_constant: 0.0 });
+        for index in 0..side * side {
+            let position = DVec3::new((index % side) as f64, (index / side) as f64, 0.0) * spacing;
+            simulator.add_particle(Particle::new(position, velocity(index), 1.0, 0.1));
+        }
+        simulator
+    }
+
+    #[test]
+    fn erf_matches_known_values() {
+        assert!(close(erf(0.0), 0.0, 1e-6));
+        assert!(close(erf(0.5), 0.520_499_877_8, 1e-6));
+        assert!(close(erf(-1.0), -0.842_700_792_9, 1e-6));
+        assert!(close(erf(3.0), 0.999_977_909_5, 1e-6));
+    }
+
+    #[test]
+    fn boltzmann_cdf_runs_from_zero_to_one() {
+        for dimension in [Dimension::Two, Dimension::Three] {
+            assert!(close(boltzmann_cdf(0.0, dimension), 0.0, 1e-6));
+            assert!(close(boltzmann_cdf(50.0, dimension), 1.0, 1e-6));
+            assert!(boltzmann_cdf(1.0, dimension) < boltzmann_cdf(2.0, dimension));
+        }
+    }
+
+    #[test]
+    fn volume_prefers_finite_bounds() {
+        let mut simulator = lattice(3, 2.0, |_| DVec3::ZERO);
+        assert_eq!(volume(&simulator), Some(16.0));
+        simulator.set_bounds(Some(Bounds::new(DVec3::new(0.0, 0.0, f64::NEG_INFINITY), DVec3::new(10.0, 5.0, f64::INFINITY))));
+        assert_eq!(volume(&simulator), Some(50.0));
+        assert_eq!(volume(&PSim::new()), None);
+    }
+
+    #[test]
+    fn histograms_need_moving_particles() {
+        let simulator = lattice(3, 2.0, |_| DVec3::ZERO);
+        assert!(speed_histogram(&simulator, 10).is_none());
+        assert!(energy_histogram(&simulator, 10).is_none());
+        assert!(speed_histogram(&PSim::new(), 10).is_none());
+    }
+
+    #[test]
+    fn speed_histogram_counts_every_moving_particle() {
+        let simulator = lattice(4, 2.0, |index| DVec3::new(index as f64 % 5.0, 0.0, 0.0));
+        let histogram = speed_histogram(&simulator, 4).unwrap();
+        assert_eq!(histogram.get_values().iter().sum::<f64>(), 16.0);
+        assert_eq!(histogram.get_bin_width(), 1.0);
+        // The fastest particles land in the last bin rather than past it
+        assert_eq!(histogram.get_values()[3], 6.0);
+        let expected: f64 = histogram.get_expected().unwrap().iter().sum();
+        assert!(expected > 0.0 && expected <= 16.0);
+    }
+
+    #[test]
+    fn radial_distribution_peaks_at_the_lattice_spacing() {
+        let simulator = lattice(10, 2.0, |_| DVec3::ZERO);
+        let histogram = radial_distribution(&simulator, 3.0, 6).unwrap();
+        let values = histogram.get_values();
+        // Nothing closer than one spacing, the neighbours sit in the bin from 2.0 to 2.5
+        assert!(values[..4].iter().all(|value| *value == 0.0));
+        assert!(values[4] > 1.0);
+        assert!(radial_distribution(&lattice(1, 2.0, |_| DVec3::ZERO), 3.0, 6).is_none());
+    }
+
+    #[test]
+    fn virial_pressure_of_free_particles_is_the_ideal_gas_law() {
+        let simulator = lattice(5, 10.0, |index| DVec3::new(1.0, (index % 2) as f64, 0.0));
+        let pressure = virial_pressure(&simulator).unwrap();
+        let volume = volume(&simulator).unwrap();
+        // P V = N k_B T with k_B = 1
+        assert!(close(pressure * volume, 25.0 * simulator.temperature(), 1e-9));
+    }
+
+    #[test]
+    fn collision_statistics_count_the_events() {
+        let simulator = lattice(2, 10.0, |_| DVec3::new(2.0, 0.0, 0.0));
+        let ids: Vec<u64> = simulator.get_particles().keys().copied().collect();
+        let collision = SimEvent::Collision { first: ids[0], second: ids[1], impulse: DVec3::X, contact_point: DVec3::ZERO };
+        let statistics = collision_statistics(&simulator, &[collision, collision], 2.0).unwrap();
+        assert_eq!(statistics.get_collisions(), 2);
+        // Each collision involves two of the four particles
+        assert_eq!(statistics.get_frequency(), 0.5);
+        assert_eq!(statistics.get_mean_free_path(), Some(4.0));
+        assert!(statistics.get_pressure() > 0.0);
+        assert!(collision_statistics(&simulator, &[], 0.0).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand_distr::{ChiSquared, Distribution, StandardNormal};
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::integrator::gaussian_vector;
+use crate::psim::simulator::particle::Particle;
+
+// `temperature` is the target, `coupling_time` sets how quickly the system is pulled towards it
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Thermostat {
+    // Stochastic rescaling of Bussi, Donadio and Parrinello, samples the canonical ensemble
+    VelocityRescaling { temperature: f64, coupling_time: f64 },
+    // Exponential relaxation of the kinetic temperature, smooth but suppresses its fluctuations
+    Berendsen { temperature: f64, coupling_time: f64 },
+    // Friction variable driven by the temperature error, the coupling time is the period of its oscillation
+    NoseHoover { temperature: f64, coupling_time: f64 },
+    // Every particle collides with the heat bath at a rate of one per coupling time
+    Andersen { temperature: f64, coupling_time: f64 },
+}
+
+pub fn degrees_of_freedom(particles: &HashMap<u64, Particle>, dimension: Dimension) -> usize {
+    particles.values().filter(|particle| !particle.is_static()).count() * dimension.count()
+}
+
+// Kinetic temperature, T = 2 K / (N_f k_B)
+pub fn temperature(particles: &HashMap<u64, Particle>, dimension: Dimension, boltzmann_constant: f64) -> f64 {
+    let degrees_of_freedom = degrees_of_freedom(particles, dimension);
+    if degrees_of_freedom == 0 {
+        return 0.0;
+    }
+    let kinetic_energy: f64 = particles.values()
+        .filter(|particle| !particle.is_static())
+        .map(|particle| particle.kinetic_energy())
+        .sum();
+    2.0 * kinetic_energy / (degrees_of_freedom as f64 * boltzmann_constant)
+}
+
+fn rescale(particles: &mut HashMap<u64, Particle>, factor: f64) {
+    for particle in particles.values_mut() {
+        let velocity = *particle.get_velocity();
+        particle.set_velocity(velocity * factor);
+    }
+}
+
+impl Thermostat {
+    pub fn get_temperature(&self) -> f64 {
+        match self {
+            Thermostat::VelocityRescaling { temperature, .. }
+            | Thermostat::Berendsen { temperature, .. }
+            | Thermostat::NoseHoover { temperature, .. }
+            | Thermostat::Andersen { temperature, .. } => *temperature,
+        }
+    }
+
+    pub fn get_coupling_time(&self) -> f64 {
+        match self {
+            Thermostat::VelocityRescaling { coupling_time, .. }
+            | Thermostat::Berendsen { coupling_time, .. }
+            | Thermostat::NoseHoover { coupling_time, .. }
+            | Thermostat::Andersen { coupling_time, .. } => *coupling_time,
+        }
+    }
+
+    // Nosé–Hoover divides by the target temperature and every kind by the coupling time
+    pub fn is_valid(&self) -> bool {
+        let temperature = self.get_temperature();
+        let temperature_valid = match self {
+            Thermostat::NoseHoover { .. } => temperature > 0.0,
+            _ => temperature >= 0.0,
+        };
+        temperature_valid && self.get_coupling_time() > 0.0
+    }
+
+    // Acts on the velocities after they were advanced by dt, `friction` carries the Nosé–Hoover state between steps.
+    // Does nothing unless `is_valid`
+    pub fn apply(&self, particles: &mut HashMap<u64, Particle>, dimension: Dimension, boltzmann_constant: f64, dt: f64, friction: &mut f64, rng: &mut StdRng) {
+        let degrees_of_freedom = degrees_of_freedom(particles, dimension);
+        if degrees_of_freedom == 0 || !self.is_valid() {
+            return;
+        }
+        let target = self.get_temperature();
+        let coupling_time = self.get_coupling_time();
+        let current = temperature(particles, dimension, boltzmann_constant);
+
+        match self {
+            Thermostat::VelocityRescaling { .. } => {
+                if current <= 0.0 {
+                    return;
+                }
+                let count = degrees_of_freedom as f64;
+                let kinetic_energy = 0.5 * count * boltzmann_constant * current;
+                let target_kinetic_energy = 0.5 * count * boltzmann_constant * target;
+                let decay = (-dt / coupling_time).exp();
+                let first: f64 = rng.sample(StandardNormal);
+                let rest = if degrees_of_freedom > 1 {
+                    ChiSquared::new(count - 1.0).unwrap().sample(rng)
+                } else {
+                    0.0
+                };
+                let new_kinetic_energy = kinetic_energy
+                    + (1.0 - decay) * (target_kinetic_energy * (first * first + rest) / count - kinetic_energy)
+                    + 2.0 * first * (decay * (1.0 - decay) * kinetic_energy * target_kinetic_energy / count).sqrt();
+                rescale(particles, (new_kinetic_energy.max(0.0) / kinetic_energy).sqrt());
+            }
+            Thermostat::Berendsen { .. } => {
+                if current <= 0.0 {
+                    return;
+                }
+                let factor = 1.0 + dt / coupling_time * (target / current - 1.0);
+                rescale(particles, factor.max(0.0).sqrt());
+            }
+            Thermostat::NoseHoover { .. } => {
+                *friction += dt / (coupling_time * coupling_time) * (current / target - 1.0);
+                rescale(particles, (-*friction * dt).exp());
+            }
+            Thermostat::Andersen { .. } => {
+                let probability = 1.0 - (-dt / coupling_time).exp();
+                for particle in particles.values_mut() {
+                    if particle.is_static() || rng.gen::<f64>() >= probability {
+                        continue;
+                    }
+                    // Fresh velocity from the Maxwell–Boltzmann distribution at the target temperature
+                    let sigma = (boltzmann_constant * target / particle.get_mass()).sqrt();
+                    particle.set_velocity(gaussian_vector(rng, dimension) * sigma);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Thermostat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Thermostat::VelocityRescaling { .. } => "Velocity rescaling",
+            Thermostat::Berendsen { .. } => "Berendsen",
+            Thermostat::NoseHoover { .. } => "Nosé–Hoover",
+            Thermostat::Andersen { .. } => "Andersen",
+        };
+        write!(f, "{} (T = {:.4e}, tau = {:.4e})", name, self.get_temperature(), self.get_coupling_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::DVec3;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn gas() -> HashMap<u64, Particle> {
+        (0..20).map(|id| {
+            let velocity = DVec3::new((id % 5) as f64 - 2.0, (id % 3) as f64 - 1.0, 0.0);
+            (id, Particle::new(DVec3::new(id as f64 * 10.0, 0.0, 0.0), velocity, 1.0, 1.0))
+        }).collect()
+    }
+
+    #[test]
+    fn rejects_targets_it_cannot_reach() {
+        assert!(!Thermostat::NoseHoover { temperature: 0.0, coupling_time: 1.0 }.is_valid());
+        assert!(!Thermostat::Berendsen { temperature: -1.0, coupling_time: 1.0 }.is_valid());
+        assert!(!Thermostat::Andersen { temperature: 1.0, coupling_time: 0.0 }.is_valid());
+        assert!(Thermostat::Berendsen { temperature: 0.0, coupling_time: 1.0 }.is_valid());
+        assert!(Thermostat::NoseHoover { temperature: 1.0, coupling_time: 1.0 }.is_valid());
+    }
+
+    #[test]
+    fn invalid_thermostat_leaves_velocities_alone() {
+        let mut particles = gas();
+        let before = temperature(&particles, Dimension::Two, 1.0);
+        let thermostat = Thermostat::NoseHoover { temperature: 0.0, coupling_time: 1.0 };
+        let (mut friction, mut rng) = (0.0, StdRng::seed_from_u64(0));
+        for _ in 0..3 {
+            thermostat.apply(&mut particles, Dimension::Two, 1.0, 0.01, &mut friction, &mut rng);
+        }
+        assert_eq!(temperature(&particles, Dimension::Two, 1.0), before);
+        assert_eq!(friction, 0.0);
+    }
+
+    #[test]
+    fn nose_hoover_pulls_towards_the_target() {
+        let mut particles = gas();
+        let before = temperature(&particles, Dimension::Two, 1.0);
+        let thermostat = Thermostat::NoseHoover { temperature: before / 2.0, coupling_time: 0.1 };
+        let (mut friction, mut rng) = (0.0, StdRng::seed_from_u64(0));
+        for _ in 0..10 {
+            thermostat.apply(&mut particles, Dimension::Two, 1.0, 0.01, &mut friction, &mut rng);
+        }
+        let after = temperature(&particles, Dimension::Two, 1.0);
+        assert!(after.is_finite() && after < before);
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::{HashMap, VecDeque};
+
+use glam::DVec3;
+
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::psim::{PSim, StepState};
+
+// Particles that differ from the frame before, most only move so they only store where to.
+// The step state changes every step, so each delta keeps its own copy
+#[derive(Clone)]
+struct Delta {
+    time: f64,
+    moved: Vec<(u64, DVec3, DVec3)>,
+    changed: Vec<(u64, Particle)>,
+    removed: Vec<u64>,
+    step_state: Box<StepState>,
+}
+
+#[derive(Clone)]
+enum Frame {
+    Keyframe(Box<PSim>),
+    Delta(Delta),
+}
+
+impl Frame {
+    fn get_time(&self) -> f64 {
+        match self {
+            Frame::Keyframe(simulator) => simulator.get_time(),
+            Frame::Delta(delta) => delta.time,
+        }
+    }
+}
+
+// Bounded history of past states, recorded every `interval` steps. Every `keyframe_interval`-th frame is
+// a full copy of the simulator and the frames in between only hold particle changes and the step state,
+// so anything else that changes between keyframes has to be announced with `request_keyframe`
+pub struct Timeline {
+    interval: usize,
+    capacity: usize,
+    keyframe_interval: usize,
+    frames: VecDeque<Frame>,
+    // Particles as of the last recorded frame, the base of the next delta
+    last_particles: HashMap<u64, Particle>,
+    steps_since_record: usize,
+    frames_since_keyframe: usize,
+    keyframe_requested: bool,
+}
+
+impl Timeline {
+    pub fn new(interval: usize, capacity: usize, keyframe_interval: usize) -> Self {
+        Timeline {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            keyframe_interval: keyframe_interval.max(1),
+            frames: VecDeque::new(),
+            last_particles: HashMap::new(),
+            steps_since_record: 0,
+            frames_since_keyframe: 0,
+            keyframe_requested: true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn get_time(&self, index: usize) -> Option<f64> {
+        self.frames.get(index).map(Frame::get_time)
+    }
+
+    // The next recorded frame stores the whole simulator, for edits beyond moving particles around
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    // Call once per step, only every `interval`-th call stores a frame
+    pub fn record(&mut self, simulator: &PSim) {
+        self.steps_since_record += 1;
+        if self.steps_since_record < self.interval && !self.frames.is_empty() {
+            return;
+        }
+        self.steps_since_record = 0;
+
+        let frame = if self.keyframe_requested || self.frames_since_keyframe + 1 >= self.keyframe_interval || self.frames.is_empty() {
+            self.keyframe_requested = false;
+            self.frames_since_keyframe = 0;
+            Frame::Keyframe(Box::new(simulator.clone()))
+        } else {
+            self.frames_since_keyframe += 1;
+            Frame::Delta(self.delta(simulator))
+        };
+        self.last_particles = simulator.get_particles().clone();
+        self.frames.push_back(frame);
+
+        if self.frames.len() > self.capacity {
+            // The oldest keyframe goes, so the delta after it has to become one
+            if let Some(Frame::Delta(_)) = self.frames.get(1) {
+                if let Some(state) = self.restore(1) {
+                    self.frames[1] = Frame::Keyframe(Box::new(state));
+                }
+            }
+            self.frames.pop_front();
+        }
+    }
+
+    fn delta(&self, simulator: &PSim) -> Delta {
+        let mut moved = vec![];
+        let mut changed = vec![];
+        for (id, particle) in simulator.get_particles() {
+            match self.last_particles.get(id) {
+                Some(previous) => {
+                    let mut expected = *previous;
+                    expected.set_pos(*particle.get_pos());
+                    expected.set_velocity(*particle.get_velocity());
+                    if expected == *particle {
+                        if previous.get_pos() != particle.get_pos() || previous.get_velocity() != particle.get_velocity() {
+                            moved.push((*id, *particle.get_pos(), *particle.get_velocity()));
+                        }
+                    } else {
+                        changed.push((*id, *particle));
+                    }
+                }
+                None => changed.push((*id, *particle)),
+            }
+        }
+        let removed = self.last_particles.keys()
+            .filter(|id| !simulator.get_particles().contains_key(id))
+            .copied()
+            .collect();
+        Delta { time: simulator.get_time(), moved, changed, removed, step_state: Box::new(simulator.get_step_state()) }
+    }
+
+    // The simulator as it was at frame `index`, replayed from the keyframe before it
+    pub fn restore(&self, index: usize) -> Option<PSim> {
+        if index >= self.frames.len() {
+            return None;
+        }
+        let keyframe = (0..=index).rev().find(|&frame| matches!(self.frames[frame], Frame::Keyframe(_)))?;
+        let mut simulator = match &self.frames[keyframe] {
+            Frame::Keyframe(simulator) => simulator.as_ref().clone(),
+            Frame::Delta(_) => return None,
+        };
+        if keyframe == index {
+            return Some(simulator);
+        }
+        for frame in self.frames.range(keyframe + 1..=index) {
+            if let Frame::Delta(delta) = frame {
+                for (id, position, velocity) in &delta.moved {
+                    if let Some(particle) = simulator.particles.get_mut(id) {
+                        particle.set_pos(*position);
+                        particle.set_velocity(*velocity);
+                    }
+                }
+                for (id, particle) in &delta.changed {
+                    simulator.particles.insert(*id, *particle);
+                }
+                for id in &delta.removed {
+                    simulator.particles.remove(id);
+                }
+                simulator.set_time(delta.time);
+            }
+        }
+        simulator.clear_step_caches();
+        if let Frame::Delta(delta) = &self.frames[index] {
+            simulator.set_step_state(delta.step_state.as_ref().clone());
+        }
+        Some(simulator)
+    }
+
+    // Drops the frames after `index`, to carry on from there on a different branch
+    pub fn truncate(&mut self, index: usize) {
+        self.frames.truncate(index + 1);
+        self.keyframe_requested = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.last_particles.clear();
+        self.keyframe_requested = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::psim::simulator::forcefield::{ForceField, ForceType, Shape};
+    use crate::psim::simulator::integrator::Integrator;
+    use crate::psim::simulator::thermostat::Thermostat;
+    use crate::psim::simulator::units::UnitSystem;
+
+    use super::*;
+
+    fn warm_gas() -> PSim {
+        let mut simulator = PSim::new();
+        simulator.set_units(UnitSystem::NBody);
+        simulator.set_seed(7);
+        simulator.set_integrator(Integrator::Langevin { friction: 0.5, temperature: 1.0 });
+        simulator.set_thermostat(Some(Thermostat::NoseHoover { temperature: 2.0, coupling_time: 0.5 }));
+        for i in 0..10 {
+            simulator.add_particle(Particle::new(DVec3::new(i as f64 * 5.0, (i % 3) as f64, 0.0), DVec3::ZERO, 1.0, 0.5));
+        }
+        simulator
+    }
+
+    fn run(simulator: &mut PSim, steps: usize, timeline: &mut Timeline) {
+        for _ in 0..steps {
+            simulator.add_forces();
+            simulator.step(0.01);
+            timeline.record(simulator);
+        }
+    }
+
+    fn positions(simulator: &PSim) -> Vec<(u64, DVec3)> {
+        let mut positions: Vec<(u64, DVec3)> = simulator.get_particles().iter().map(|(id, particle)| (*id, *particle.get_pos())).collect();
+        positions.sort_by_key(|(id, _)| *id);
+        positions
+    }
+
+    #[test]
+    fn branching_from_a_delta_repeats_the_original_run() {
+        let mut timeline = Timeline::new(1, 100, 100);
+        let mut simulator = warm_gas();
+        run(&mut simulator, 20, &mut timeline);
+        let mut branch = timeline.restore(9).unwrap();
+        assert_eq!(branch.get_time(), timeline.get_time(9).unwrap());
+        run(&mut branch, 10, &mut Timeline::new(1, 100, 100));
+        assert_eq!(positions(&branch), positions(&simulator));
+    }
+
+    #[test]
+    fn full_timeline_drops_the_oldest_frames() {
+        let mut timeline = Timeline::new(1, 10, 4);
+        let mut simulator = warm_gas();
+        run(&mut simulator, 25, &mut timeline);
+        assert_eq!(timeline.len(), 10);
+        let oldest = timeline.restore(0).unwrap();
+        assert_eq!(oldest.get_time(), timeline.get_time(0).unwrap());
+        assert_eq!(positions(&timeline.restore(9).unwrap()), positions(&simulator));
+    }
+
+    #[test]
+    fn removed_particles_stay_removed_when_restored() {
+        let mut timeline = Timeline::new(1, 100, 100);
+        let mut simulator = warm_gas();
+        run(&mut simulator, 5, &mut timeline);
+        let id = *simulator.get_particles().keys().next().unwrap();
+        simulator.remove_particle(id);
+        run(&mut simulator, 5, &mut timeline);
+        assert!(timeline.restore(4).unwrap().get_particles().contains_key(&id));
+        assert!(!timeline.restore(5).unwrap().get_particles().contains_key(&id));
+    }
+
+    #[test]
+    fn requested_keyframe_keeps_changes_beyond_particles() {
+        let mut timeline = Timeline::new(1, 100, 100);
+        let mut simulator = warm_gas();
+        run(&mut simulator, 5, &mut timeline);
+        simulator.add_force_field(ForceField::new(DVec3::ZERO, Shape::Circle { radius: 10.0 }, ForceType::Force { force: DVec3::X }));
+        timeline.request_keyframe();
+        run(&mut simulator, 5, &mut timeline);
+        assert!(timeline.restore(4).unwrap().get_force_fields().is_empty());
+        assert_eq!(timeline.restore(7).unwrap().get_force_fields().len(), 1);
+    }
+}
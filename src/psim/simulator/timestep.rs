@@ -0,0 +1,23 @@
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TimestepCriterion {
+    // dt = eta * sqrt(length / |a|) and dt = courant * length / |v|, whichever is smaller
+    Kinematic { eta: f64, courant: f64 },
+    // Position error of the first order update, estimated from the change in acceleration since the last step
+    ErrorEstimate { tolerance: f64 },
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Timestep {
+    Fixed,
+    Adaptive { criterion: TimestepCriterion, min_dt: f64, max_dt: f64 },
+}
+
+impl Timestep {
+    pub fn is_adaptive(&self) -> bool {
+        matches!(self, Timestep::Adaptive { .. })
+    }
+}
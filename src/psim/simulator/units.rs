@@ -0,0 +1,95 @@
+use core::fmt;
+use std::f64::consts::PI;
+
+use physical_constants::{BOLTZMANN_CONSTANT, NEWTONIAN_CONSTANT_OF_GRAVITATION};
+
+const ASTRONOMICAL_UNIT: f64 = 1.495978707e11;
+const SOLAR_MASS: f64 = 1.98847e30;
+const JULIAN_YEAR: f64 = 3.15576e7;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UnitSystem {
+    // Metres, kilograms, seconds
+    #[cfg_attr(feature = "serde", serde(rename = "si"))]
+    SI,
+    // Astronomical units, solar masses, years
+    Astronomical,
+    // Dimensionless N-body units with G = 1
+    #[cfg_attr(feature = "serde", serde(rename = "nbody"))]
+    NBody,
+    Custom { gravitational_constant: f64 },
+}
+
+impl UnitSystem {
+    pub fn gravitational_constant(&self) -> f64 {
+        match self {
+            UnitSystem::SI => NEWTONIAN_CONSTANT_OF_GRAVITATION,
+            // Kepler's third law with a one year orbit at 1 AU
+            UnitSystem::Astronomical => 4.0 * PI * PI,
+            UnitSystem::NBody => 1.0,
+            UnitSystem::Custom { gravitational_constant } => *gravitational_constant,
+        }
+    }
+
+    // Temperatures are in kelvin where the units have one, otherwise in energy units with k_B = 1
+    pub fn boltzmann_constant(&self) -> f64 {
+        match self {
+            UnitSystem::SI => BOLTZMANN_CONSTANT,
+            UnitSystem::Astronomical => BOLTZMANN_CONSTANT * JULIAN_YEAR * JULIAN_YEAR / (SOLAR_MASS * ASTRONOMICAL_UNIT * ASTRONOMICAL_UNIT),
+            UnitSystem::NBody | UnitSystem::Custom { .. } => 1.0,
+        }
+    }
+
+    pub fn length_unit(&self) -> &'static str {
+        match self {
+            UnitSystem::SI => "m",
+            UnitSystem::Astronomical => "AU",
+            UnitSystem::NBody | UnitSystem::Custom { .. } => "L",
+        }
+    }
+
+    pub fn mass_unit(&self) -> &'static str {
+        match self {
+            UnitSystem::SI => "kg",
+            UnitSystem::Astronomical => "M_sun",
+            UnitSystem::NBody | UnitSystem::Custom { .. } => "M",
+        }
+    }
+
+    pub fn time_unit(&self) -> &'static str {
+        match self {
+            UnitSystem::SI => "s",
+            UnitSystem::Astronomical => "yr",
+            UnitSystem::NBody | UnitSystem::Custom { .. } => "T",
+        }
+    }
+
+    pub fn energy_unit(&self) -> &'static str {
+        match self {
+            UnitSystem::SI => "J",
+            UnitSystem::Astronomical => "M_sun AU^2/yr^2",
+            UnitSystem::NBody | UnitSystem::Custom { .. } => "M L^2/T^2",
+        }
+    }
+
+    pub fn temperature_unit(&self) -> &'static str {
+        match self {
+            UnitSystem::SI | UnitSystem::Astronomical => "K",
+            UnitSystem::NBody | UnitSystem::Custom { .. } => "M L^2/T^2",
+        }
+    }
+}
+
+impl fmt::Display for UnitSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UnitSystem::SI => "SI",
+            UnitSystem::Astronomical => "Astronomical",
+            UnitSystem::NBody => "N-body",
+            UnitSystem::Custom { .. } => "Custom",
+        };
+        write!(f, "{} ({}, {}, {}, G = {:.4e})", name, self.length_unit(), self.mass_unit(), self.time_unit(), self.gravitational_constant())
+    }
+}
@@ -1,62 +1,299 @@
 use ggez::{Context, GameResult, graphics};
 use ggez::event::{EventHandler, MouseButton};
-use ggez::glam::{vec2, Vec2};
+use glam::{vec2, DVec3, Vec2};
+use rand::random;
 use ggez::graphics::{Canvas, Color, Rect, Text, TextFragment, PxScale, Drawable};
-use ggez::input::keyboard::{KeyCode, KeyInput};
-use crate::psim::gui::Gui;
-use crate::psim::simulator::forcefield::{ForceField, Shape};
+use ggez::input::keyboard::{KeyCode, KeyInput, KeyMods};
+use crate::psim::gui::{ColorMode, Gui, PredictionMode};
+use crate::psim::history::{Edit, History, ParticleProperty};
+#[cfg(feature = "scripting")]
+use crate::psim::scripting::Scenario;
+use crate::psim::simulator::forcefield::{ForceField, ForceType, Shape};
+use crate::psim::simulator::generators::{Generator, PRESET_NAMES};
+use crate::psim::simulator::integrator::Integrator;
+use crate::psim::simulator::obstacle::Obstacle;
+use crate::psim::simulator::orbit::KeplerElements;
 use crate::psim::simulator::particle::Particle;
-use crate::psim::simulator::psim::PSim;
+use crate::psim::simulator::prediction::Predictor;
+use crate::psim::simulator::events::SimEvent;
+use crate::psim::simulator::psim::{Bounds, PSim};
+use crate::psim::simulator::softening::Softening;
+use crate::psim::simulator::statistics::{speed_histogram, virial_pressure};
+use crate::psim::simulator::timeline::Timeline;
+use crate::psim::simulator::species::{Interaction, InteractionMatrix};
+use crate::psim::simulator::sph::{EquationOfState, Sph};
+use crate::psim::simulator::timestep::{Timestep, TimestepCriterion};
+use crate::psim::simulator::units::UnitSystem;
 
 const DEFAULT_PARTICLE_RADIUS: f64 = 2.0;
-const DEFAULT_PARTICLE_MASS: f64 = 15000.0;
-const DEFAULT_PARTICLE_VELOCITY: Vec2 = Vec2 { x: 0.0, y: 0.0 };
+const DEFAULT_PARTICLE_MASS: f64 = 1e-4;
+const DEFAULT_PARTICLE_VELOCITY: DVec3 = DVec3 { x: 0.0, y: 0.0, z: 0.0 };
 const DEFAULT_BIG_PARTICLE_RADIUS: f64 = 100.0;
-const DEFAULT_BIG_PARTICLE_MASS: f64 = 20.0 * 1e14;
+const DEFAULT_BIG_PARTICLE_MASS: f64 = 1.3e7;
 const DEFAULT_GRAVITY_RADIUS: f64 = 40.0;
-const DEFAULT_GRAVITY_MASS: f64 = 8.0 * 1e13;
+const DEFAULT_GRAVITY_MASS: f64 = 5.3e5;
+const DEFAULT_FLUID_PARTICLE_RADIUS: f64 = 1.5;
+const DEFAULT_FLUID_PARTICLE_MASS: f64 = 1.0;
+const DEFAULT_FLUID_SPACING: f64 = 4.0;
+const DEFAULT_FLUID_BLOCK_SIZE: usize = 8;
+// Rest density matches the spacing above, m / spacing²
+const DEFAULT_SPH_SMOOTHING_LENGTH: f64 = 5.0;
+const DEFAULT_SPH_REST_DENSITY: f64 = 0.0625;
+const DEFAULT_SPH_EQUATION_OF_STATE: EquationOfState = EquationOfState::Tait { sound_speed: 100.0, gamma: 7.0 };
+const PARTICLE_LIFE_SPECIES: usize = 5;
+const PARTICLE_LIFE_COUNT: usize = 400;
+const PARTICLE_LIFE_RADIUS: f64 = 3.0;
+const PARTICLE_LIFE_RANGE: f64 = 80.0;
+const PARTICLE_LIFE_STRENGTH: f64 = 300.0;
+const GALTON_ROWS: usize = 10;
+const GALTON_SPACING: f64 = 24.0;
+const GALTON_PEG_RADIUS: f64 = 4.0;
+const GALTON_BIN_DEPTH: f64 = 150.0;
+const GALTON_GRAVITY: f64 = 300.0;
+// Obstacles within this many pixels of the cursor are removed with D
+const OBSTACLE_PICK_DISTANCE: f64 = 5.0;
+// Langevin friction at zero temperature, keeps the self-organised structures from heating up
+const PARTICLE_LIFE_FRICTION: f64 = 4.0;
+// Number keys spawn the generator presets, in the order of `PRESET_NAMES`
+const GENERATOR_KEYS: [KeyCode; 7] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6, KeyCode::Key7];
+// Steps simulated ahead for the predicted paths, keeping every PREDICTION_STRIDE-th position
+const PREDICTION_STEPS: usize = 2000;
+const PREDICTION_STRIDE: usize = 10;
+// A frame every TIMELINE_INTERVAL steps, a full copy of the scene every TIMELINE_KEYFRAME_INTERVAL frames
+const TIMELINE_INTERVAL: usize = 10;
+const TIMELINE_CAPACITY: usize = 600;
+const TIMELINE_KEYFRAME_INTERVAL: usize = 50;
+// Keys that change the scene and so carry on from a rewound frame, besides the generators and Ctrl+Z/Y.
+// Space changes nothing but carries on as well, the rest only change how the scene is shown.
+// Timeline deltas record particles coming, going and changing, so only the second kind needs a keyframe
+const PARTICLE_EDIT_KEYS: [KeyCode; 8] = [
+    KeyCode::Space, KeyCode::P, KeyCode::O, KeyCode::G, KeyCode::K, KeyCode::S, KeyCode::H, KeyCode::D,
+];
+const SCENE_EDIT_KEYS: [KeyCode; 6] = [KeyCode::F, KeyCode::B, KeyCode::L, KeyCode::R, KeyCode::C, KeyCode::T];
+// Speed histogram in the top right corner
+const STATISTICS_BINS: usize = 20;
+const STATISTICS_PLOT_WIDTH: f32 = 300.0;
+const STATISTICS_PLOT_HEIGHT: f32 = 150.0;
+// Pixels for length, G = 1
+const DEFAULT_UNITS: UnitSystem = UnitSystem::NBody;
+const DEFAULT_SOFTENING: Softening = Softening::Plummer { length: 2.0 };
+const DEFAULT_ADAPTIVE_TIMESTEP: Timestep = Timestep::Adaptive {
+    criterion: TimestepCriterion::Kinematic { eta: 0.2, courant: 0.5 },
+    min_dt: 1e-5,
+    max_dt: 0.05,
+};
 const COLOR_BACKGROUND: Color = Color { r: 0.2, g: 0.2, b: 0.2, a: 1.0 };
 const COLOR_PARTICLE: Color = Color { r: 0.9, g: 0.9, b: 0.6, a: 1.0 };
 const COLOR_FORCE_FIELD: Color = Color { r: 0.2, g: 0.5, b: 0.9, a: 1.0 };
+const COLOR_PREDICTION: Color = Color { r: 0.6, g: 0.9, b: 0.6, a: 0.6 };
+const COLOR_HISTOGRAM: Color = Color { r: 0.9, g: 0.6, b: 0.3, a: 0.8 };
+const COLOR_MAXWELL_BOLTZMANN: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+const COLOR_ERROR: Color = Color { r: 0.95, g: 0.3, b: 0.3, a: 1.0 };
+const COLOR_OBSTACLE: Color = Color { r: 0.85, g: 0.85, b: 0.85, a: 1.0 };
+const COLOR_SPECIES: [Color; 6] = [
+    Color { r: 0.9, g: 0.3, b: 0.3, a: 1.0 },
+    Color { r: 0.3, g: 0.8, b: 0.3, a: 1.0 },
+    Color { r: 0.3, g: 0.5, b: 0.95, a: 1.0 },
+    Color { r: 0.95, g: 0.85, b: 0.3, a: 1.0 },
+    Color { r: 0.8, g: 0.4, b: 0.9, a: 1.0 },
+    Color { r: 0.3, g: 0.85, b: 0.85, a: 1.0 },
+];
+
+// Blue at the lowest value on screen, red at the highest
+fn heat_color(value: f64, min: f64, max: f64) -> Color {
+    let t = if max > min { ((value - min) / (max - min)) as f32 } else { 0.5 };
+    Color::new(t, 0.2, 1.0 - t, 1.0)
+}
 
 pub struct Visualizer {
-    mouse_position: Vec2,
+    mouse_position: DVec3,
     simulator: PSim,
     settings: Gui,
+    collision_count: usize,
+    predictor: Predictor,
+    // Set whenever the scene may have changed since the last prediction started
+    prediction_stale: bool,
+    // Edits made from the keyboard, scenario scripts change the scene without going through it
+    history: History,
+    timeline: Timeline,
+    // Frame of the timeline on screen while rewound, with the simulator as it was before rewinding
+    rewind: Option<usize>,
+    live: Option<PSim>,
+    #[cfg(feature = "scripting")]
+    scenario: Option<Scenario>,
+}
+
+fn new_simulator(size: Vec2) -> PSim {
+    let mut simulator = PSim::new();
+    simulator.set_units(DEFAULT_UNITS);
+    simulator.set_softening(DEFAULT_SOFTENING);
+    // Bounds only apply to the visible plane, depth is unbounded
+    simulator.set_bounds(Some(Bounds::new(
+        DVec3::new(0.0, 0.0, f64::NEG_INFINITY),
+        DVec3::new(size.x as f64, size.y as f64, f64::INFINITY),
+    )));
+    simulator
 }
 
 impl Visualizer {
     pub fn new(width: u32, height: u32, dt: f64, realtime: bool) -> GameResult<Self> {
         //create drawable or canvas
+        let size = Vec2::new(width as f32, height as f32);
         Ok(Visualizer {
-            simulator: PSim::new(),
-            mouse_position: Vec2::new(0.0, 0.0),
-            settings: Gui::new(Vec2::new(width as f32, height as f32), 1.0, dt, realtime),
+            simulator: new_simulator(size),
+            mouse_position: DVec3::ZERO,
+            settings: Gui::new(size, 1.0, dt, realtime),
+            collision_count: 0,
+            predictor: Predictor::new(),
+            prediction_stale: true,
+            history: History::new(),
+            timeline: Timeline::new(TIMELINE_INTERVAL, TIMELINE_CAPACITY, TIMELINE_KEYFRAME_INTERVAL),
+            rewind: None,
+            live: None,
+            #[cfg(feature = "scripting")]
+            scenario: None,
         })
     }
+
+    #[cfg(feature = "scripting")]
+    pub fn load_scenario(&mut self, path: &str) -> Result<(), Box<rhai::EvalAltResult>> {
+        self.settings.set_scenario_error(None);
+        self.timeline.request_keyframe();
+        self.scenario = Some(Scenario::load(path, &mut self.simulator)?);
+        Ok(())
+    }
     pub fn add_particle(&mut self, particle: Particle) {
-        self.simulator.add_particle(particle);
+        self.history.apply(&mut self.simulator, Edit::AddParticle { id: random(), particle });
     }
 
     pub fn add_force_field(&mut self, force_field: ForceField) {
-        self.simulator.add_force_field(force_field);
+        let index = self.simulator.get_force_fields().len();
+        self.history.apply(&mut self.simulator, Edit::AddForceField { index, force_field });
+    }
+
+    // Records particles that were added straight to the simulator as a single edit
+    fn record_added(&mut self, ids: &[u64]) {
+        let edits = ids.iter()
+            .filter_map(|id| self.simulator.get_particles().get(id).map(|particle| Edit::AddParticle { id: *id, particle: *particle }))
+            .collect();
+        self.history.record(Edit::Group(edits));
+    }
+
+    // Changes a property of the particle under the cursor, `property` gets the particle as it is now
+    fn set_property(&mut self, property: impl Fn(&Particle) -> ParticleProperty) {
+        if let Some(&id) = self.simulator.particles_at(&self.mouse_position).first() {
+            let particle = &self.simulator.get_particles()[&id];
+            let after = property(particle);
+            let edit = Edit::SetProperty { id, before: after.current(particle), after };
+            self.history.apply(&mut self.simulator, edit);
+        }
+    }
+
+    // Funnel, a triangle of pegs and bins below, with its own downward pull, hanging from `top`
+    fn add_galton_board(&mut self, top: DVec3) {
+        let point = |x: f64, y: f64| top + DVec3::new(x, y, 0.0);
+        let half_width = (GALTON_ROWS + 2) as f64 * GALTON_SPACING / 2.0;
+        let pegs_top = 100.0;
+        let bins_top = pegs_top + GALTON_ROWS as f64 * GALTON_SPACING;
+
+        let mut obstacles = vec![
+            Obstacle::Segment { start: point(-half_width, 0.0), end: point(-GALTON_SPACING / 3.0, 60.0) },
+            Obstacle::Segment { start: point(half_width, 0.0), end: point(GALTON_SPACING / 3.0, 60.0) },
+        ];
+        for row in 0..GALTON_ROWS {
+            for peg in 0..=row {
+                let centre = point((peg as f64 - row as f64 / 2.0) * GALTON_SPACING, pegs_top + row as f64 * GALTON_SPACING);
+                let points = (0..6)
+                    .map(|corner| {
+                        let angle = corner as f64 * std::f64::consts::PI / 3.0;
+                        centre + DVec3::new(angle.cos(), angle.sin(), 0.0) * GALTON_PEG_RADIUS
+                    })
+                    .collect();
+                obstacles.push(Obstacle::Polygon { points });
+            }
+        }
+        for wall in 0..=GALTON_ROWS + 1 {
+            let x = (wall as f64 - (GALTON_ROWS + 1) as f64 / 2.0) * GALTON_SPACING;
+            obstacles.push(Obstacle::Segment { start: point(x, bins_top), end: point(x, bins_top + GALTON_BIN_DEPTH) });
+        }
+        obstacles.push(Obstacle::Segment {
+            start: point(-half_width, bins_top + GALTON_BIN_DEPTH),
+            end: point(half_width, bins_top + GALTON_BIN_DEPTH),
+        });
+
+        let height = bins_top + GALTON_BIN_DEPTH;
+        let force_field = ForceField::new(
+            point(0.0, height / 2.0),
+            Shape::Rectangle { width: 2.0 * half_width, height },
+            ForceType::Force { force: DVec3::new(0.0, GALTON_GRAVITY * DEFAULT_PARTICLE_MASS, 0.0) },
+        );
+        // One edit for the whole board
+        let first_obstacle = self.simulator.get_obstacles().len();
+        let mut edits: Vec<Edit> = obstacles.into_iter().enumerate()
+            .map(|(offset, obstacle)| Edit::AddObstacle { index: first_obstacle + offset, obstacle })
+            .collect();
+        edits.push(Edit::AddForceField { index: self.simulator.get_force_fields().len(), force_field });
+        self.history.apply(&mut self.simulator, Edit::Group(edits));
+    }
+
+    fn show_frame(&mut self, index: usize) {
+        if let Some(simulator) = self.timeline.restore(index) {
+            self.simulator = simulator;
+            self.rewind = Some(index);
+        }
+    }
+
+    fn step_back(&mut self) {
+        match self.rewind {
+            Some(index) => self.show_frame(index.saturating_sub(1)),
+            None if !self.timeline.is_empty() => {
+                self.live = Some(self.simulator.clone());
+                self.show_frame(self.timeline.len() - 1);
+            }
+            None => {}
+        }
+    }
+
+    // Past the last frame is the simulator as it was before rewinding
+    fn step_forward(&mut self) {
+        if let Some(index) = self.rewind {
+            if index + 1 < self.timeline.len() {
+                self.show_frame(index + 1);
+            } else if let Some(live) = self.live.take() {
+                self.simulator = live;
+                self.rewind = None;
+            }
+        }
+    }
+
+    // Carries on from the frame on screen, the frames after it and the edits made since are dropped
+    fn branch(&mut self) {
+        if let Some(index) = self.rewind.take() {
+            self.timeline.truncate(index);
+            self.live = None;
+            self.history.clear();
+        }
+    }
+
+    fn update_active_particle_data(&mut self) {
+        let active_particle_id = self.settings.get_active_particle_id();
+        if let Some(active_particle) = self.simulator.particles.get(&active_particle_id) {
+            let orbit = self.simulator.orbit(active_particle_id, None);
+            self.settings.set_active_particle_data(*active_particle, orbit, *self.simulator.get_units());
+        }
     }
 
     fn clean(&mut self) {
         //remove particles out of bounds
-        let size = self.settings.get_size();
-        self.simulator.particles.retain(|_, particle| {
-            let pos = particle.get_pos();
-            let size = self.settings.get_size();
-            pos.x >= 0.0 && pos.x <= size.x && pos.y >= 0.0 && pos.y <= size.y
-        })
+        self.simulator.remove_out_of_bounds();
     }
 
     fn draw_simulator(&mut self, ctx: &mut Context, canvas: &mut Canvas) -> GameResult {
         self.simulator.get_force_fields().iter().for_each(|force_field| {
             let pos = force_field.get_pos();
             match &force_field.get_shape() {
-                Shape::Circle { radius } => {
+                // Drawn as their projection onto the xy plane
+                Shape::Circle { radius } | Shape::Sphere { radius } => {
                     let circle_mesh = graphics::Mesh::new_circle(
                         ctx,
                         graphics::DrawMode::fill(),
@@ -65,9 +302,9 @@ impl Visualizer {
                         0.0,
                         COLOR_FORCE_FIELD,
                     ).unwrap();
-                    canvas.draw(&circle_mesh, Vec2::new(pos.x, pos.y));
+                    canvas.draw(&circle_mesh, pos.truncate().as_vec2());
                 }
-                Shape::Rectangle { width, height } => {
+                Shape::Rectangle { width, height } | Shape::Box { width, height, .. } => {
                     let rectangle = Rect::new(
                         -(width / 2.0) as f32,
                         -(height / 2.0) as f32,
@@ -80,19 +317,67 @@ impl Visualizer {
                         rectangle,
                         COLOR_FORCE_FIELD,
                     ).unwrap();
-                    canvas.draw(&rectangle_mesh, Vec2::new(pos.x, pos.y));
+                    canvas.draw(&rectangle_mesh, pos.truncate().as_vec2());
                 }
             }
         });
 
-        //draw particle
-        self.simulator.get_particles().iter().for_each(|(id, particle)| {
+        for obstacle in self.simulator.get_obstacles() {
+            let mut points: Vec<Vec2> = match obstacle {
+                Obstacle::Segment { start, end } => vec![start.truncate().as_vec2(), end.truncate().as_vec2()],
+                Obstacle::Polyline { points } | Obstacle::Polygon { points } => points.iter().map(|point| point.truncate().as_vec2()).collect(),
+            };
+            if let Some(first) = points.first().filter(|_| matches!(obstacle, Obstacle::Polygon { .. })) {
+                points.push(*first);
+            }
+            if points.len() >= 2 {
+                let line_mesh = graphics::Mesh::new_line(ctx, &points, 2.0, COLOR_OBSTACLE)?;
+                canvas.draw(&line_mesh, Vec2::ZERO);
+            }
+        }
+
+        let paths: Vec<&Vec<DVec3>> = match self.settings.get_prediction_mode() {
+            PredictionMode::Off => vec![],
+            PredictionMode::Active => self.predictor.get_path(self.settings.get_active_particle_id()).into_iter().collect(),
+            PredictionMode::All => self.predictor.get_paths().values().collect(),
+        };
+        for path in paths {
+            let mut points: Vec<Vec2> = path.iter().map(|point| point.truncate().as_vec2()).collect();
+            // Resting particles would give zero length segments
+            points.dedup();
+            if points.len() >= 2 {
+                let line_mesh = graphics::Mesh::new_line(ctx, &points, 1.0, COLOR_PREDICTION)?;
+                canvas.draw(&line_mesh, Vec2::ZERO);
+            }
+        }
+
+        //draw particle, furthest from the viewer first
+        let mut particles: Vec<_> = self.simulator.get_particles().iter().collect();
+        particles.sort_by(|(_, a), (_, b)| a.get_pos().z.total_cmp(&b.get_pos().z));
+        let color_mode = self.settings.get_color_mode();
+        let fluid_value = |particle: &Particle| match color_mode {
+            ColorMode::Density => particle.get_density(),
+            ColorMode::Pressure => particle.get_pressure(),
+            ColorMode::Plain => 0.0,
+        };
+        let (min_value, max_value) = particles.iter()
+            .filter(|(_, particle)| particle.is_fluid())
+            .map(|(_, particle)| fluid_value(particle))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+        particles.into_iter().for_each(|(id, particle)| {
             let pos = particle.get_pos();
-            let color = if id == &self.settings.get_active_particle_id() {
+            let mut color = if id == &self.settings.get_active_particle_id() {
                 Color::BLACK
+            } else if particle.is_fluid() && color_mode != ColorMode::Plain {
+                heat_color(fluid_value(particle), min_value, max_value)
+            } else if self.simulator.get_interactions().is_some() {
+                COLOR_SPECIES[particle.get_species() % COLOR_SPECIES.len()]
             } else {
                 COLOR_PARTICLE
             };
+            if particle.is_ghost() {
+                color.a = 0.4;
+            }
 
             let circle_mesh = graphics::Mesh::new_circle(
                 ctx,
@@ -102,18 +387,65 @@ impl Visualizer {
                 0.1,
                 color,
             ).unwrap();
-            canvas.draw(&circle_mesh, Vec2::new(pos.x as f32, pos.y as f32));
+            canvas.draw(&circle_mesh, pos.truncate().as_vec2());
         });
         Ok(())
     }
 
+    // Measured speeds against the Maxwell–Boltzmann distribution at the current temperature
+    fn draw_statistics(&mut self, ctx: &mut Context, canvas: &mut Canvas) -> GameResult {
+        let histogram = match speed_histogram(&self.simulator, STATISTICS_BINS) {
+            Some(histogram) => histogram,
+            None => return Ok(()),
+        };
+        let values = histogram.get_values();
+        let tallest = values.iter()
+            .chain(histogram.get_expected().into_iter().flatten())
+            .fold(0.0, |tallest: f64, value| tallest.max(*value));
+        let origin = Vec2::new(self.settings.get_size().x - STATISTICS_PLOT_WIDTH - 10.0, 10.0 + STATISTICS_PLOT_HEIGHT);
+        let bar_width = STATISTICS_PLOT_WIDTH / values.len() as f32;
+        let bar_height = |value: f64| (value / tallest) as f32 * STATISTICS_PLOT_HEIGHT;
+        for (bin, value) in values.iter().enumerate() {
+            if *value > 0.0 {
+                let bar = Rect::new(bin as f32 * bar_width, -bar_height(*value), bar_width, bar_height(*value));
+                let bar_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bar, COLOR_HISTOGRAM)?;
+                canvas.draw(&bar_mesh, origin);
+            }
+        }
+        if let Some(expected) = histogram.get_expected() {
+            let points: Vec<Vec2> = expected.iter().enumerate()
+                .map(|(bin, value)| Vec2::new((bin as f32 + 0.5) * bar_width, -bar_height(*value)))
+                .collect();
+            if points.len() >= 2 {
+                let line_mesh = graphics::Mesh::new_line(ctx, &points, 2.0, COLOR_MAXWELL_BOLTZMANN)?;
+                canvas.draw(&line_mesh, origin);
+            }
+        }
+
+        let units = self.simulator.get_units();
+        let text_statistics = Text::new(TextFragment {
+            text: format!(
+                "Speed [{}/{}] up to {:.3e}\nPressure (virial): {:.4e}",
+                units.length_unit(),
+                units.time_unit(),
+                histogram.get_bin_width() * values.len() as f64,
+                virial_pressure(&self.simulator).unwrap_or(0.0),
+            ),
+            color: Some(Color::WHITE),
+            font: Some("LiberationMono-Regular".into()),
+            scale: Some(PxScale::from(16.0)),
+        });
+        canvas.draw(&text_statistics, origin + Vec2::new(0.0, 5.0));
+        Ok(())
+    }
+
     fn draw_gui(&mut self, ctx: &mut Context, canvas: &mut Canvas) -> GameResult {
         let size = self.settings.get_size();
         let rectangle = Rect::new(
             0.0,
             0.0,
             size.x,
-            -170.0,
+            -320.0,
         );
         let rectangle_mesh = graphics::Mesh::new_rectangle(
             ctx,
@@ -126,7 +458,24 @@ impl Visualizer {
         // Create a Font object using the system font
         let frametime = ctx.time.delta().as_secs_f64();
         let text_performance = Text::new(TextFragment {
-            text: format!("Frametime: {}\nFPS: {:.2}\nParticles: {}", frametime, 1.0 / frametime,self.simulator.particles.len()),
+            text: format!(
+                "Frametime: {}\nFPS: {:.2}\nParticles: {} Collisions: {}\nUnits: {}\nTime: {:.3} {} dt: {:.2e}{}{}\nEnergy [{}]:\n{}",
+                frametime,
+                1.0 / frametime,
+                self.simulator.particles.len(),
+                self.collision_count,
+                self.simulator.get_units(),
+                self.simulator.get_time(),
+                self.simulator.get_units().time_unit(),
+                self.simulator.get_last_dt(),
+                if self.simulator.get_timestep().is_adaptive() { " (adaptive)" } else { "" },
+                match self.rewind {
+                    Some(index) => format!(" rewound: frame {}/{}", index + 1, self.timeline.len()),
+                    None => String::new(),
+                },
+                self.simulator.get_units().energy_unit(),
+                self.simulator.diagnostics(),
+            ),
             color: Some(Color::BLACK),
             font: Some("LiberationMono-Regular".into()),
             scale: Some(PxScale::from(20.0)),
@@ -145,31 +494,57 @@ impl Visualizer {
             let text_particle_size =text_particle.dimensions(ctx).unwrap().size();
             canvas.draw(&text_particle, Vec2::new(size.x/2.0, size.y - text_particle_size.y));
         }
-        
+
+        if let Some(error) = self.settings.get_scenario_error() {
+            let text_error = Text::new(TextFragment {
+                text: format!("Scenario stopped: {}", error),
+                color: Some(COLOR_ERROR),
+                font: Some("LiberationMono-Regular".into()),
+                scale: Some(PxScale::from(20.0)),
+            });
+            canvas.draw(&text_error, Vec2::new(10.0, 10.0));
+        }
+
         Ok(())
     }
 }
 
 impl EventHandler for Visualizer {
     fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
-        match input.keycode.unwrap() {
+        self.prediction_stale = true;
+        let keycode = input.keycode.unwrap();
+        // Undo and redo may bring back a field, an obstacle or a whole snapshot
+        let (edit, scene_edit) = match keycode {
+            KeyCode::Z | KeyCode::Y => (input.mods.contains(KeyMods::CTRL), input.mods.contains(KeyMods::CTRL)),
+            keycode if SCENE_EDIT_KEYS.contains(&keycode) => (true, true),
+            keycode => (PARTICLE_EDIT_KEYS.contains(&keycode) || GENERATOR_KEYS.contains(&keycode), false),
+        };
+        if scene_edit {
+            self.timeline.request_keyframe();
+        }
+        if edit {
+            self.branch();
+        }
+        match keycode {
             KeyCode::Escape => {
                 self.settings.set_running(false);
                 ctx.request_quit();
             }
             KeyCode::P => {
-                self.add_particle(Particle::new(
-                    Vec2::new(self.mouse_position.x, self.mouse_position.y),
+                let mut particle = Particle::new(
+                    self.mouse_position,
                     DEFAULT_PARTICLE_VELOCITY,
                     DEFAULT_PARTICLE_MASS,
                     DEFAULT_PARTICLE_RADIUS,
-                ),
                 );
+                // Small particles are the ones fast enough to tunnel
+                particle.set_continuous_collision(true);
+                self.add_particle(particle);
             }
             KeyCode::O => {
                 self.add_particle(Particle::new(
-                    Vec2::new(self.mouse_position.x, self.mouse_position.y),
-                    Vec2::new(0.0, 0.0),
+                    self.mouse_position,
+                    DVec3::ZERO,
                     DEFAULT_BIG_PARTICLE_MASS,
                     DEFAULT_BIG_PARTICLE_RADIUS,
                 ),
@@ -177,40 +552,162 @@ impl EventHandler for Visualizer {
             }
             KeyCode::G => {
                 self.add_particle(Particle::new(
-                    Vec2::new(self.mouse_position.x, self.mouse_position.y),
-                    Vec2::new(0.0, 0.0),
+                    self.mouse_position,
+                    DVec3::ZERO,
                     DEFAULT_GRAVITY_MASS,
                     DEFAULT_GRAVITY_RADIUS,
                 ),
                 );
             }
+            KeyCode::K => {
+                // Circular orbit through the cursor around whichever body pulls hardest there
+                if let Some(central) = self.simulator.dominant_body(&self.mouse_position) {
+                    let offset = self.mouse_position - *self.simulator.get_particles()[&central].get_pos();
+                    let elements = KeplerElements::circular(offset.truncate().length(), offset.y.atan2(offset.x));
+                    if let Some(id) = self.simulator.add_orbiting_particle(central, &elements, DEFAULT_PARTICLE_MASS, DEFAULT_PARTICLE_RADIUS) {
+                        self.record_added(&[id]);
+                    }
+                }
+            }
+            keycode if GENERATOR_KEYS.contains(&keycode) => {
+                // Centred on the cursor, with a fresh seed every time
+                let index = GENERATOR_KEYS.iter().position(|key| *key == keycode).unwrap();
+                if let Some(generator) = Generator::preset(PRESET_NAMES[index], self.mouse_position) {
+                    let ids = generator.populate(&mut self.simulator, random());
+                    self.record_added(&ids);
+                }
+            }
+            KeyCode::F => {
+                if self.simulator.get_sph().is_none() {
+                    self.simulator.set_sph(Some(Sph::new(
+                        DEFAULT_SPH_SMOOTHING_LENGTH,
+                        DEFAULT_SPH_REST_DENSITY,
+                        DEFAULT_SPH_EQUATION_OF_STATE,
+                    )));
+                }
+                // Square block of fluid centred on the cursor
+                let half_size = (DEFAULT_FLUID_BLOCK_SIZE - 1) as f64 * DEFAULT_FLUID_SPACING / 2.0;
+                let mut edits = vec![];
+                for i in 0..DEFAULT_FLUID_BLOCK_SIZE {
+                    for j in 0..DEFAULT_FLUID_BLOCK_SIZE {
+                        let offset = DVec3::new(i as f64, j as f64, 0.0) * DEFAULT_FLUID_SPACING - DVec3::new(half_size, half_size, 0.0);
+                        let mut particle = Particle::new(
+                            self.mouse_position + offset,
+                            DVec3::ZERO,
+                            DEFAULT_FLUID_PARTICLE_MASS,
+                            DEFAULT_FLUID_PARTICLE_RADIUS,
+                        );
+                        particle.set_fluid(true);
+                        edits.push(Edit::AddParticle { id: random(), particle });
+                    }
+                }
+                // The solver stays on after an undo, without fluid particles it does nothing
+                self.history.apply(&mut self.simulator, Edit::Group(edits));
+            }
+            KeyCode::B => {
+                self.add_galton_board(self.mouse_position);
+            }
+            KeyCode::L => {
+                // Random species matrix over the whole window, a fresh "particle life" every time.
+                // It also swaps the integrator, so undo restores the whole scene from before
+                let before = Box::new(self.simulator.clone());
+                let mut interactions = InteractionMatrix::new(PARTICLE_LIFE_SPECIES, Interaction::NONE);
+                for species in 0..PARTICLE_LIFE_SPECIES {
+                    for other in 0..PARTICLE_LIFE_SPECIES {
+                        let strength = PARTICLE_LIFE_STRENGTH * (2.0 * random::<f64>() - 1.0);
+                        interactions.set(species, other, Interaction::new(strength, PARTICLE_LIFE_RANGE, false));
+                    }
+                }
+                self.simulator.set_interactions(Some(interactions));
+                self.simulator.set_integrator(Integrator::Langevin { friction: PARTICLE_LIFE_FRICTION, temperature: 0.0 });
+                let size = self.settings.get_size();
+                for _ in 0..PARTICLE_LIFE_COUNT {
+                    let position = DVec3::new(random::<f64>() * size.x as f64, random::<f64>() * size.y as f64, 0.0);
+                    let mut particle = Particle::new(position, DVec3::ZERO, DEFAULT_PARTICLE_MASS, PARTICLE_LIFE_RADIUS);
+                    particle.set_species(random::<usize>() % PARTICLE_LIFE_SPECIES);
+                    self.simulator.add_particle(particle);
+                }
+                self.history.record(Edit::Snapshot { before, after: Box::new(self.simulator.clone()) });
+            }
+            KeyCode::LBracket => {
+                self.step_back();
+            }
+            KeyCode::RBracket => {
+                self.step_forward();
+            }
+            KeyCode::M => {
+                self.settings.set_show_statistics(!self.settings.get_show_statistics());
+            }
+            KeyCode::V => {
+                self.settings.set_color_mode(self.settings.get_color_mode().next());
+            }
+            KeyCode::N => {
+                self.settings.set_prediction_mode(self.settings.get_prediction_mode().next());
+                if self.settings.get_prediction_mode() == PredictionMode::Off {
+                    self.predictor.clear();
+                }
+            }
             KeyCode::R => {
-                self.simulator = PSim::new();
+                let timestep = *self.simulator.get_timestep();
+                let continuous_collision = self.simulator.get_continuous_collision();
+                let before = Box::new(std::mem::replace(&mut self.simulator, new_simulator(self.settings.get_size())));
+                self.simulator.set_timestep(timestep);
+                #[cfg(feature = "scripting")]
+                {
+                    self.scenario = None;
+                    self.settings.set_scenario_error(None);
+                }
+                self.simulator.set_continuous_collision(continuous_collision);
+                // A stopped scenario isn't brought back by undo, only the scene it built
+                self.history.record(Edit::Snapshot { before, after: Box::new(self.simulator.clone()) });
+            }
+            KeyCode::Z if input.mods.contains(KeyMods::CTRL) => {
+                self.history.undo(&mut self.simulator);
+            }
+            KeyCode::Y if input.mods.contains(KeyMods::CTRL) => {
+                self.history.redo(&mut self.simulator);
+            }
+            KeyCode::S => {
+                self.set_property(|particle| ParticleProperty::Static(!particle.is_static()));
+            }
+            KeyCode::H => {
+                self.set_property(|particle| ParticleProperty::Ghost(!particle.is_ghost()));
+            }
+            KeyCode::C => {
+                let continuous_collision = self.simulator.get_continuous_collision();
+                self.simulator.set_continuous_collision(!continuous_collision);
+            }
+            KeyCode::T => {
+                if self.simulator.get_timestep().is_adaptive() {
+                    self.simulator.set_timestep(Timestep::Fixed);
+                } else {
+                    self.simulator.set_timestep(DEFAULT_ADAPTIVE_TIMESTEP);
+                }
             }
             KeyCode::D => {
-                let ids_to_remove: Vec<u64> = self.simulator.particles.iter()
-                    .filter(|(&id, particle)| {
-                        particle.get_pos().distance(self.mouse_position) as f64 <= particle.get_radius()
-                    })
-                    .map(|(&id, _)| id)
+                let mut edits: Vec<Edit> = self.simulator.particles_at(&self.mouse_position).into_iter()
+                    .map(|id| Edit::RemoveParticle { id, particle: self.simulator.get_particles()[&id] })
                     .collect();
 
-                for id in ids_to_remove {
-                    self.simulator.particles.remove(&id);
-                }
+                // Highest index first so the ones left to remove keep theirs
+                edits.extend(self.simulator.fields_containing(&self.mouse_position).into_iter().rev().map(|index| Edit::RemoveForceField {
+                    index,
+                    force_field: self.simulator.get_force_fields()[index].clone(),
+                }));
 
-                self.simulator.force_fields.retain(|force_field| {
-                    let force_field_pos: &Vec2 = force_field.get_pos();
-                    match force_field.get_shape() {
-                        Shape::Circle { radius } => {
-                            force_field_pos.distance(self.mouse_position) as f64 > *radius
-                        }
-                        Shape::Rectangle { width, height } => {
-                            !((force_field_pos.x >= self.mouse_position.x - (width / 2.0) as f32 && force_field_pos.x <= self.mouse_position.x + (width / 2.0) as f32) &&
-                                (force_field_pos.y >= self.mouse_position.y - (height / 2.0) as f32 && force_field_pos.y <= self.mouse_position.y + (height / 2.0) as f32))
-                        }
-                    }
-                })
+                let obstacles_to_remove: Vec<usize> = self.simulator.get_obstacles().iter().enumerate()
+                    .filter(|(_, obstacle)| obstacle.distance(&self.mouse_position) <= OBSTACLE_PICK_DISTANCE)
+                    .map(|(index, _)| index)
+                    .collect();
+
+                edits.extend(obstacles_to_remove.into_iter().rev().map(|index| Edit::RemoveObstacle {
+                    index,
+                    obstacle: self.simulator.get_obstacles()[index].clone(),
+                }));
+                if edits.iter().any(|edit| !matches!(edit, Edit::RemoveParticle { .. })) {
+                    self.timeline.request_keyframe();
+                }
+                self.history.apply(&mut self.simulator, Edit::Group(edits));
             }
             _ => {}
         }
@@ -218,40 +715,67 @@ impl EventHandler for Visualizer {
     }
 
     fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) -> GameResult {
-        self.mouse_position.x = x;
-        self.mouse_position.y = y;
+        self.mouse_position.x = x as f64;
+        self.mouse_position.y = y as f64;
         Ok(())
     }
 
     fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
-        match button {
-            MouseButton::Left => {
-                self.simulator.particles.iter().find(|(_, particle)| {
-                    (particle.get_pos().distance(self.mouse_position) as f64) < particle.get_radius()
-                }).map(|(id, _)| {
-                    self.settings.set_active_particle_id(*id);
-                });
+        if button == MouseButton::Left {
+            if let Some(id) = self.simulator.particles_at(&self.mouse_position).first() {
+                self.settings.set_active_particle_id(*id);
             }
-            _ => {}
         }
         Ok(())
     }
     fn update(&mut self, _ctx: &mut Context) -> GameResult {
+        if self.rewind.is_some() {
+            // Paused on a past frame
+            self.update_active_particle_data();
+            return Ok(());
+        }
         self.clean();
-        let dt: f64;
-        if self.settings.get_realtime() {
-            dt = _ctx.time.delta().as_secs_f64();
+        let dt = if self.settings.get_realtime() {
+            _ctx.time.delta().as_secs_f64()
         } else {
-            dt = self.settings.get_dt();
+            self.settings.get_dt()
+        };
+
+        // Restarted once the previous prediction is done, if the scene changed or half of it has been played out.
+        // Scenario scripts aren't run ahead, so their effects only show after the next restart
+        if self.settings.get_prediction_mode() != PredictionMode::Off {
+            self.predictor.poll();
+            let played_out = self.simulator.get_time() - self.predictor.get_start_time() > PREDICTION_STEPS as f64 * dt / 2.0;
+            if !self.predictor.is_running() && (self.prediction_stale || played_out) {
+                self.predictor.start(&self.simulator, PREDICTION_STEPS, dt, PREDICTION_STRIDE);
+                self.prediction_stale = false;
+            }
         }
+
         self.simulator.add_forces();
+        self.update_active_particle_data();
 
-        let active_particle_id = self.settings.get_active_particle_id();
-        self.simulator.particles.get(&active_particle_id).map(|active_particle| {
-            self.settings.set_active_particle_data(active_particle.clone());
-        });
+        #[cfg(feature = "scripting")]
+        if let Some(scenario) = &mut self.scenario {
+            let result = scenario.step(&mut self.simulator);
+            // Deltas between keyframes only hold particles, so anything else the script did needs a keyframe
+            if scenario.take_scene_changed() {
+                self.timeline.request_keyframe();
+            }
+            if let Err(error) = result {
+                self.settings.set_scenario_error(Some(error.to_string()));
+                self.scenario = None;
+            } else if scenario.is_stopped() {
+                self.simulator.reset_forces();
+                return Ok(());
+            }
+        }
 
         self.simulator.step(dt);
+        self.timeline.record(&self.simulator);
+        self.collision_count = self.simulator.drain_events().iter()
+            .filter(|event| matches!(event, SimEvent::Collision { .. }))
+            .count();
         Ok(())
     }
 
@@ -262,6 +786,9 @@ impl EventHandler for Visualizer {
         //draw force fields
         self.draw_simulator(ctx, &mut canvas)?;
         self.draw_gui(ctx, &mut canvas)?;
+        if self.settings.get_show_statistics() {
+            self.draw_statistics(ctx, &mut canvas)?;
+        }
 
         canvas.finish(ctx).unwrap();
         Ok(())
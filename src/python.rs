@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+
+use glam::DVec3;
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyArrayMethods};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::psim::simulator::dimension::Dimension;
+use crate::psim::simulator::events::SimEvent;
+use crate::psim::simulator::forcefield::{ForceField, ForceType, Shape};
+use crate::psim::simulator::generators::Generator;
+use crate::psim::simulator::integrator::Integrator;
+use crate::psim::simulator::orbit::KeplerElements;
+use crate::psim::simulator::particle::Particle;
+use crate::psim::simulator::prediction;
+use crate::psim::simulator::psim::PSim;
+use crate::psim::simulator::softening::Softening;
+use crate::psim::simulator::statistics::{self, Histogram};
+use crate::psim::simulator::thermostat::Thermostat;
+use crate::psim::simulator::units::UnitSystem;
+
+fn to_vec3(values: [f64; 3]) -> DVec3 {
+    DVec3::from_array(values)
+}
+
+fn histogram_dict<'py>(py: Python<'py>, histogram: Option<Histogram>) -> PyResult<Option<Bound<'py, PyDict>>> {
+    let histogram = match histogram {
+        Some(histogram) => histogram,
+        None => return Ok(None),
+    };
+    let dict = PyDict::new(py);
+    dict.set_item("centers", histogram.get_centers())?;
+    dict.set_item("bin_width", histogram.get_bin_width())?;
+    dict.set_item("values", histogram.get_values().clone())?;
+    dict.set_item("expected", histogram.get_expected().cloned())?;
+    Ok(Some(dict))
+}
+
+#[pyclass(name = "Particle")]
+#[derive(Clone)]
+struct PyParticle {
+    particle: Particle,
+}
+
+#[pymethods]
+impl PyParticle {
+    #[new]
+    #[pyo3(signature = (position, velocity, mass, radius, is_static = false, ghost = false))]
+    fn new(position: [f64; 3], velocity: [f64; 3], mass: f64, radius: f64, is_static: bool, ghost: bool) -> Self {
+        let mut particle = if is_static {
+            Particle::new_static(to_vec3(position), to_vec3(velocity), mass, radius)
+        } else {
+            Particle::new(to_vec3(position), to_vec3(velocity), mass, radius)
+        };
+        particle.set_ghost(ghost);
+        PyParticle { particle }
+    }
+
+    #[getter]
+    fn position(&self) -> [f64; 3] {
+        self.particle.get_pos().to_array()
+    }
+
+    #[getter]
+    fn velocity(&self) -> [f64; 3] {
+        self.particle.get_velocity().to_array()
+    }
+
+    #[getter]
+    fn acceleration(&self) -> [f64; 3] {
+        self.particle.get_acceleration().to_array()
+    }
+
+    #[getter]
+    fn mass(&self) -> f64 {
+        self.particle.get_mass()
+    }
+
+    #[getter]
+    fn radius(&self) -> f64 {
+        self.particle.get_radius()
+    }
+
+    #[getter]
+    fn is_static(&self) -> bool {
+        self.particle.is_static()
+    }
+
+    #[getter]
+    fn ghost(&self) -> bool {
+        self.particle.is_ghost()
+    }
+}
+
+#[pyclass(name = "ForceField")]
+#[derive(Clone)]
+struct PyForceField {
+    force_field: ForceField,
+}
+
+fn force_type(mass: Option<f64>, force: Option<[f64; 3]>) -> PyResult<ForceType> {
+    match (mass, force) {
+        (Some(mass), None) => Ok(ForceType::Gravity { mass }),
+        (None, Some(force)) => Ok(ForceType::Force { force: to_vec3(force) }),
+        _ => Err(PyValueError::new_err("exactly one of `mass` or `force` must be given")),
+    }
+}
+
+#[pymethods]
+impl PyForceField {
+    #[staticmethod]
+    #[pyo3(signature = (position, radius, *, mass = None, force = None))]
+    fn circle(position: [f64; 3], radius: f64, mass: Option<f64>, force: Option<[f64; 3]>) -> PyResult<Self> {
+        let force_field = ForceField::new(to_vec3(position), Shape::Circle { radius }, force_type(mass, force)?);
+        Ok(PyForceField { force_field })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (position, width, height, *, mass = None, force = None))]
+    fn rectangle(position: [f64; 3], width: f64, height: f64, mass: Option<f64>, force: Option<[f64; 3]>) -> PyResult<Self> {
+        let force_field = ForceField::new(to_vec3(position), Shape::Rectangle { width, height }, force_type(mass, force)?);
+        Ok(PyForceField { force_field })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (position, radius, *, mass = None, force = None))]
+    fn sphere(position: [f64; 3], radius: f64, mass: Option<f64>, force: Option<[f64; 3]>) -> PyResult<Self> {
+        let force_field = ForceField::new(to_vec3(position), Shape::Sphere { radius }, force_type(mass, force)?);
+        Ok(PyForceField { force_field })
+    }
+
+    #[staticmethod]
+    #[pyo3(name = "box", signature = (position, width, height, depth, *, mass = None, force = None))]
+    fn cuboid(position: [f64; 3], width: f64, height: f64, depth: f64, mass: Option<f64>, force: Option<[f64; 3]>) -> PyResult<Self> {
+        let force_field = ForceField::new(to_vec3(position), Shape::Box { width, height, depth }, force_type(mass, force)?);
+        Ok(PyForceField { force_field })
+    }
+
+    #[getter]
+    fn position(&self) -> [f64; 3] {
+        self.force_field.get_pos().to_array()
+    }
+
+    #[getter]
+    fn active(&self) -> bool {
+        self.force_field.is_active()
+    }
+}
+
+#[pyclass(name = "KeplerElements")]
+#[derive(Clone)]
+struct PyKeplerElements {
+    elements: KeplerElements,
+}
+
+#[pymethods]
+impl PyKeplerElements {
+    #[new]
+    #[pyo3(signature = (semi_major_axis, eccentricity = 0.0, argument_of_periapsis = 0.0, true_anomaly = 0.0))]
+    fn new(semi_major_axis: f64, eccentricity: f64, argument_of_periapsis: f64, true_anomaly: f64) -> Self {
+        PyKeplerElements { elements: KeplerElements::new(semi_major_axis, eccentricity, argument_of_periapsis, true_anomaly) }
+    }
+
+    #[getter]
+    fn semi_major_axis(&self) -> f64 {
+        self.elements.semi_major_axis
+    }
+
+    #[getter]
+    fn eccentricity(&self) -> f64 {
+        self.elements.eccentricity
+    }
+
+    #[getter]
+    fn argument_of_periapsis(&self) -> f64 {
+        self.elements.argument_of_periapsis
+    }
+
+    #[getter]
+    fn true_anomaly(&self) -> f64 {
+        self.elements.true_anomaly
+    }
+}
+
+// PSim holds non-Sync event listeners, so it stays on the thread that created it
+#[pyclass(name = "PSim", unsendable)]
+struct PyPSim {
+    simulator: PSim,
+    views: Option<Views>,
+}
+
+// Arrays handed out by `positions` and `velocities`, rewritten in place after every change for as long
+// as the same particles are there. Adding or removing particles leaves them with the last state they had
+struct Views {
+    ids: Vec<u64>,
+    positions: Py<PyArray2<f64>>,
+    velocities: Py<PyArray2<f64>>,
+}
+
+// Read-only to Python, writing to them wouldn't move the particles
+fn write_rows(array: &Bound<'_, PyArray2<f64>>, particles: &[(u64, &Particle)], vector: impl Fn(&Particle) -> DVec3) -> PyResult<()> {
+    let flags = PyDict::new(array.py());
+    flags.set_item("write", true)?;
+    array.call_method("setflags", (), Some(&flags))?;
+    {
+        let mut rows = array.readwrite();
+        for (mut row, (_, particle)) in rows.as_array_mut().rows_mut().into_iter().zip(particles) {
+            row.assign(&numpy::ndarray::arr1(&vector(particle).to_array()));
+        }
+    }
+    flags.set_item("write", false)?;
+    array.call_method("setflags", (), Some(&flags))?;
+    Ok(())
+}
+
+impl PyPSim {
+    // Particles in a stable order so the arrays returned below line up with `ids`
+    fn sorted_particles(&self) -> Vec<(u64, &Particle)> {
+        let mut particles: Vec<(u64, &Particle)> = self.simulator.get_particles().iter().map(|(id, particle)| (*id, particle)).collect();
+        particles.sort_by_key(|(id, _)| *id);
+        particles
+    }
+
+    // Arrays for the current particles, the ones handed out before if the particles are still the same
+    fn views(&mut self, py: Python<'_>) -> PyResult<&Views> {
+        let ids: Vec<u64> = self.sorted_particles().iter().map(|(id, _)| *id).collect();
+        let views = match self.views.take() {
+            Some(views) if views.ids == ids => views,
+            _ => {
+                let particles = self.sorted_particles();
+                let positions = PyArray2::<f64>::zeros(py, [particles.len(), 3], false);
+                let velocities = PyArray2::<f64>::zeros(py, [particles.len(), 3], false);
+                write_rows(&positions, &particles, |particle| *particle.get_pos())?;
+                write_rows(&velocities, &particles, |particle| *particle.get_velocity())?;
+                Views { ids, positions: positions.unbind(), velocities: velocities.unbind() }
+            }
+        };
+        Ok(self.views.insert(views))
+    }
+
+    // Call after anything that moves, adds or removes particles
+    fn refresh_views(&mut self, py: Python<'_>) -> PyResult<()> {
+        let particles = self.sorted_particles();
+        let views = match &self.views {
+            Some(views) if views.ids.iter().eq(particles.iter().map(|(id, _)| id)) => views,
+            _ => return Ok(()),
+        };
+        write_rows(views.positions.bind(py), &particles, |particle| *particle.get_pos())?;
+        write_rows(views.velocities.bind(py), &particles, |particle| *particle.get_velocity())
+    }
+}
+
+#[pymethods]
+impl PyPSim {
+    #[new]
+    fn new() -> Self {
+        PyPSim { simulator: PSim::new(), views: None }
+    }
+
+    fn __len__(&self) -> usize {
+        self.simulator.get_particles().len()
+    }
+
+    #[getter]
+    fn time(&self) -> f64 {
+        self.simulator.get_time()
+    }
+
+    #[getter]
+    fn last_dt(&self) -> f64 {
+        self.simulator.get_last_dt()
+    }
+
+    #[getter]
+    fn gravitational_constant(&self) -> f64 {
+        self.simulator.get_gravitational_constant()
+    }
+
+    fn set_units(&mut self, name: &str) -> PyResult<()> {
+        let units = match name {
+            "si" => UnitSystem::SI,
+            "astronomical" => UnitSystem::Astronomical,
+            "nbody" => UnitSystem::NBody,
+            _ => return Err(PyValueError::new_err(format!("unknown unit system '{}'", name))),
+        };
+        self.simulator.set_units(units);
+        Ok(())
+    }
+
+    fn set_gravitational_constant(&mut self, gravitational_constant: f64) {
+        self.simulator.set_units(UnitSystem::Custom { gravitational_constant });
+    }
+
+    fn set_dimension(&mut self, dimension: usize) -> PyResult<()> {
+        let dimension = match dimension {
+            2 => Dimension::Two,
+            3 => Dimension::Three,
+            _ => return Err(PyValueError::new_err(format!("unsupported dimension {}", dimension))),
+        };
+        self.simulator.set_dimension(dimension);
+        Ok(())
+    }
+
+    #[pyo3(signature = (kernel, length = 0.0))]
+    fn set_softening(&mut self, kernel: &str, length: f64) -> PyResult<()> {
+        let softening = match kernel {
+            "none" => Softening::None,
+            "plummer" => Softening::Plummer { length },
+            "spline" => Softening::Spline { length },
+            _ => return Err(PyValueError::new_err(format!("unknown softening kernel '{}'", kernel))),
+        };
+        self.simulator.set_softening(softening);
+        Ok(())
+    }
+
+    // Pass `kind = None` to remove the thermostat
+    #[pyo3(signature = (kind, temperature = 0.0, coupling_time = 1.0))]
+    fn set_thermostat(&mut self, kind: Option<&str>, temperature: f64, coupling_time: f64) -> PyResult<()> {
+        let thermostat = match kind {
+            None => None,
+            Some("velocity_rescaling") => Some(Thermostat::VelocityRescaling { temperature, coupling_time }),
+            Some("berendsen") => Some(Thermostat::Berendsen { temperature, coupling_time }),
+            Some("nose_hoover") => Some(Thermostat::NoseHoover { temperature, coupling_time }),
+            Some("andersen") => Some(Thermostat::Andersen { temperature, coupling_time }),
+            Some(kind) => return Err(PyValueError::new_err(format!("unknown thermostat '{}'", kind))),
+        };
+        if thermostat.is_some_and(|thermostat| !thermostat.is_valid()) {
+            return Err(PyValueError::new_err(
+                "thermostats need coupling_time > 0 and temperature >= 0, or temperature > 0 for nose_hoover",
+            ));
+        }
+        self.simulator.set_thermostat(thermostat);
+        Ok(())
+    }
+
+    #[pyo3(signature = (kind, friction = 1.0, temperature = 0.0))]
+    fn set_integrator(&mut self, kind: &str, friction: f64, temperature: f64) -> PyResult<()> {
+        let integrator = match kind {
+            "newtonian" => Integrator::Newtonian,
+            "langevin" => Integrator::Langevin { friction, temperature },
+            "brownian" => Integrator::Brownian { friction, temperature },
+            _ => return Err(PyValueError::new_err(format!("unknown integrator '{}'", kind))),
+        };
+        if !integrator.is_valid() {
+            return Err(PyValueError::new_err("langevin and brownian need friction > 0 and temperature >= 0"));
+        }
+        self.simulator.set_integrator(integrator);
+        Ok(())
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.simulator.set_seed(seed);
+    }
+
+    #[getter]
+    fn temperature(&self) -> f64 {
+        self.simulator.temperature()
+    }
+
+    fn add_particle(&mut self, py: Python<'_>, particle: PyParticle) -> PyResult<u64> {
+        let id = self.simulator.add_particle(particle.particle);
+        self.refresh_views(py)?;
+        Ok(id)
+    }
+
+    fn add_orbiting_particle(&mut self, py: Python<'_>, central: u64, elements: PyKeplerElements, mass: f64, radius: f64) -> PyResult<u64> {
+        let id = self.simulator.add_orbiting_particle(central, &elements.elements, mass, radius)
+            .ok_or_else(|| PyValueError::new_err(format!("cannot place an orbit around particle {}", central)))?;
+        self.refresh_views(py)?;
+        Ok(id)
+    }
+
+    // Adds one of the named presets, the ids come back in generation order
+    #[pyo3(signature = (name, center = [0.0, 0.0, 0.0], seed = 0))]
+    fn generate(&mut self, py: Python<'_>, name: &str, center: [f64; 3], seed: u64) -> PyResult<Vec<u64>> {
+        let generator = Generator::preset(name, to_vec3(center))
+            .ok_or_else(|| PyValueError::new_err(format!("unknown generator '{}'", name)))?;
+        let ids = generator.populate(&mut self.simulator, seed);
+        self.refresh_views(py)?;
+        Ok(ids)
+    }
+
+    fn remove_particle(&mut self, py: Python<'_>, id: u64) -> PyResult<Option<PyParticle>> {
+        let particle = self.simulator.remove_particle(id).map(|particle| PyParticle { particle });
+        self.refresh_views(py)?;
+        Ok(particle)
+    }
+
+    fn get_particle(&self, id: u64) -> Option<PyParticle> {
+        self.simulator.get_particles().get(&id).map(|particle| PyParticle { particle: *particle })
+    }
+
+    fn particles_within(&self, point: [f64; 3], radius: f64) -> Vec<u64> {
+        self.simulator.particles_within(&to_vec3(point), radius)
+    }
+
+    fn particles_at(&self, point: [f64; 3]) -> Vec<u64> {
+        self.simulator.particles_at(&to_vec3(point))
+    }
+
+    fn particles_in_rectangle(&self, min: [f64; 3], max: [f64; 3]) -> Vec<u64> {
+        self.simulator.particles_in_rectangle(&to_vec3(min), &to_vec3(max))
+    }
+
+    fn particles_in_polygon(&self, points: Vec<[f64; 3]>) -> Vec<u64> {
+        let points: Vec<DVec3> = points.into_iter().map(to_vec3).collect();
+        self.simulator.particles_in_polygon(&points)
+    }
+
+    fn nearest_particle(&self, point: [f64; 3]) -> Option<u64> {
+        self.simulator.nearest_particle(&to_vec3(point))
+    }
+
+    fn fields_containing(&self, point: [f64; 3]) -> Vec<usize> {
+        self.simulator.fields_containing(&to_vec3(point))
+    }
+
+    fn add_force_field(&mut self, force_field: PyForceField) -> usize {
+        self.simulator.add_force_field(force_field.force_field);
+        self.simulator.get_force_fields().len() - 1
+    }
+
+    fn set_field_active(&mut self, index: usize, active: bool) -> PyResult<()> {
+        match self.simulator.force_fields.get_mut(index) {
+            Some(force_field) => {
+                force_field.set_active(active);
+                Ok(())
+            }
+            None => Err(PyValueError::new_err(format!("no force field at index {}", index))),
+        }
+    }
+
+    // Gathers forces and advances by one step
+    fn step(&mut self, py: Python<'_>, dt: f64) -> PyResult<()> {
+        self.run(py, 1, dt)
+    }
+
+    fn run(&mut self, py: Python<'_>, steps: u64, dt: f64) -> PyResult<()> {
+        for _ in 0..steps {
+            self.simulator.add_forces();
+            self.simulator.step(dt);
+            self.simulator.drain_events();
+        }
+        self.refresh_views(py)
+    }
+
+    fn ids<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u64>> {
+        let ids: Vec<u64> = self.sorted_particles().iter().map(|(id, _)| *id).collect();
+        ids.into_pyarray(py)
+    }
+
+    // Read-only views that follow the simulation, one row per particle in the order of `ids`. Adding or
+    // removing particles detaches them, call again for the new set or `.copy()` to keep a snapshot
+    fn positions<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        Ok(self.views(py)?.positions.bind(py).clone())
+    }
+
+    fn velocities<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f64>>> {
+        Ok(self.views(py)?.velocities.bind(py).clone())
+    }
+
+    fn masses<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        let masses: Vec<f64> = self.sorted_particles().iter().map(|(_, particle)| particle.get_mass()).collect();
+        masses.into_pyarray(py)
+    }
+
+    fn diagnostics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let diagnostics = self.simulator.diagnostics();
+        let dict = PyDict::new(py);
+        dict.set_item("kinetic_energy", diagnostics.get_kinetic_energy())?;
+        dict.set_item("potential_energy", diagnostics.get_potential_energy())?;
+        dict.set_item("total_energy", diagnostics.get_total_energy())?;
+        dict.set_item("temperature", diagnostics.get_temperature())?;
+        Ok(dict)
+    }
+
+    // Counts per speed bin next to the Maxwell–Boltzmann counts at the current temperature
+    #[pyo3(signature = (bins = 20))]
+    fn speed_histogram<'py>(&self, py: Python<'py>, bins: usize) -> PyResult<Option<Bound<'py, PyDict>>> {
+        histogram_dict(py, statistics::speed_histogram(&self.simulator, bins))
+    }
+
+    #[pyo3(signature = (bins = 20))]
+    fn energy_histogram<'py>(&self, py: Python<'py>, bins: usize) -> PyResult<Option<Bound<'py, PyDict>>> {
+        histogram_dict(py, statistics::energy_histogram(&self.simulator, bins))
+    }
+
+    #[pyo3(signature = (max_radius, bins = 50))]
+    fn radial_distribution<'py>(&self, py: Python<'py>, max_radius: f64, bins: usize) -> PyResult<Option<Bound<'py, PyDict>>> {
+        histogram_dict(py, statistics::radial_distribution(&self.simulator, max_radius, bins))
+    }
+
+    fn virial_pressure(&self) -> Option<f64> {
+        statistics::virial_pressure(&self.simulator)
+    }
+
+    // Runs `steps` steps and measures the collisions over them
+    fn collision_statistics<'py>(&mut self, py: Python<'py>, steps: u64, dt: f64) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let start = self.simulator.get_time();
+        let mut events: Vec<SimEvent> = vec![];
+        for _ in 0..steps {
+            self.simulator.add_forces();
+            self.simulator.step(dt);
+            events.extend(self.simulator.drain_events());
+        }
+        self.refresh_views(py)?;
+        let collision_statistics = match statistics::collision_statistics(&self.simulator, &events, self.simulator.get_time() - start) {
+            Some(collision_statistics) => collision_statistics,
+            None => return Ok(None),
+        };
+        let dict = PyDict::new(py);
+        dict.set_item("collisions", collision_statistics.get_collisions())?;
+        dict.set_item("frequency", collision_statistics.get_frequency())?;
+        dict.set_item("mean_free_path", collision_statistics.get_mean_free_path())?;
+        dict.set_item("predicted_mean_free_path", collision_statistics.get_predicted_mean_free_path())?;
+        dict.set_item("pressure", collision_statistics.get_pressure())?;
+        Ok(Some(dict))
+    }
+
+    // Runs a copy ahead without touching this simulator, positions every `stride` steps keyed by particle id
+    #[pyo3(signature = (steps, dt, stride = 1))]
+    fn predict(&self, steps: usize, dt: f64, stride: usize) -> HashMap<u64, Vec<[f64; 3]>> {
+        prediction::predict(self.simulator.clone(), steps, dt, stride).into_iter()
+            .map(|(id, path)| (id, path.iter().map(|point| point.to_array()).collect()))
+            .collect()
+    }
+
+    // Apoapsis and period are None for unbound orbits
+    #[pyo3(signature = (id, central = None))]
+    fn orbit<'py>(&self, py: Python<'py>, id: u64, central: Option<u64>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let orbit = match self.simulator.orbit(id, central) {
+            Some(orbit) => orbit,
+            None => return Ok(None),
+        };
+        let dict = PyDict::new(py);
+        dict.set_item("central", orbit.get_central())?;
+        dict.set_item("semi_major_axis", orbit.get_semi_major_axis())?;
+        dict.set_item("eccentricity", orbit.get_eccentricity())?;
+        dict.set_item("periapsis", orbit.get_periapsis())?;
+        dict.set_item("apoapsis", orbit.get_apoapsis())?;
+        dict.set_item("period", orbit.get_period())?;
+        dict.set_item("bound", orbit.is_bound())?;
+        Ok(Some(dict))
+    }
+}
+
+#[pymodule]
+fn particle_sim(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyParticle>()?;
+    module.add_class::<PyForceField>()?;
+    module.add_class::<PyKeplerElements>()?;
+    module.add_class::<PyPSim>()?;
+    Ok(())
+}